@@ -1,11 +1,30 @@
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as instructions_sysvar, load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::associated_token::{
+    get_associated_token_address_with_program_id, AssociatedToken,
+};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use anchor_spl::token_2022_extensions::transfer_fee::{
+    harvest_withheld_tokens_to_mint, HarvestWithheldTokensToMint,
+};
+use anchor_spl::token_interface::{
+    self, CloseAccount, Mint, TokenAccount, TokenInterface, TransferChecked,
+};
 declare_id!("GJdNPUnhyjz4x47fibedUxmLY7Xx42E6SrQGfEJUzj9S");
 
 pub mod constants {
     pub const ESCROW_SEED: &[u8] = b"vault";
     pub const DATA_SEED: &[u8] = b"data_account";
+    pub const REGISTRY_SEED: &[u8] = b"registry";
+    pub const FUNDER_SEED: &[u8] = b"funder";
+    pub const CONFIG_SEED: &[u8] = b"config";
+    pub const MAX_BENEFICIARIES: usize = 96;
 }
 
 #[program]
@@ -13,48 +32,175 @@ pub mod token_claim_program {
 
     use super::*;
 
+    pub fn initialize_config(ctx: Context<InitializeConfig>, guardian: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+
+        config.guardian = guardian;
+        config.paused = false;
+
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+
+        let clock = Clock::get()?;
+
+        emit!(PauseStatusChanged {
+            guardian: ctx.accounts.guardian.key(),
+            paused,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_guardian(ctx: Context<UpdateConfig>, new_guardian: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.guardian = new_guardian;
+
+        Ok(())
+    }
+
+    pub fn initialize_registry(ctx: Context<InitializeRegistry>) -> Result<()> {
+        let registry = &mut ctx.accounts.registry;
+
+        registry.authority = ctx.accounts.authority.key();
+        registry.token_mint = ctx.accounts.token_mint.key();
+        registry.claim_types = Registry::DEFAULT_CLAIM_TYPES
+            .iter()
+            .enumerate()
+            .map(|(claim_type, label)| ClaimTypeLabel {
+                claim_type: claim_type as u8,
+                label: label.to_string(),
+            })
+            .collect();
+
+        Ok(())
+    }
+
+    pub fn set_claim_type_label(
+        ctx: Context<UpdateRegistry>,
+        claim_type: u8,
+        label: String,
+    ) -> Result<()> {
+        if label.is_empty() || label.len() > Registry::MAX_LABEL_LEN {
+            return Err(ErrorCode::InvalidLabel.into());
+        }
+
+        let registry = &mut ctx.accounts.registry;
+
+        match registry
+            .claim_types
+            .iter_mut()
+            .find(|claim_type_label| claim_type_label.claim_type == claim_type)
+        {
+            Some(claim_type_label) => claim_type_label.label = label,
+            None => registry.claim_types.push(ClaimTypeLabel { claim_type, label }),
+        }
+
+        Ok(())
+    }
+
     pub fn initialize(
         ctx: Context<Initialize>,
         round: u8,
         claim_type: u8,
         batch: u8,
-        _list_size: u64,
-        beneficiaries: Vec<Beneficiary>,
+        beneficiaries: Vec<BeneficiaryInput>,
         amount: u64,
-        decimals: u8,
     ) -> Result<()> {
-        let data_account = &mut ctx.accounts.data_account;
-        data_account.beneficiaries = beneficiaries;
+        assert_supported_mint(&ctx.accounts.token_mint.to_account_info())?;
 
-        if data_account.batch == batch {
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_init()?;
+        let registry = &mut ctx.accounts.registry;
+
+        if !registry
+            .claim_types
+            .iter()
+            .any(|claim_type_label| claim_type_label.claim_type == claim_type)
+        {
+            return Err(ErrorCode::InvalidClaimType.into());
+        }
+
+        if registry.batches.iter().any(|entry| {
+            entry.round == round && entry.claim_type == claim_type && entry.batch == batch
+        }) {
             return Err(ErrorCode::IsBatched.into());
         }
 
-        data_account.released = false;
+        // amount and allocations are raw base units of token_mint
+        let total_allocated = beneficiaries
+            .iter()
+            .try_fold(0u64, |total, beneficiary| {
+                total.checked_add(beneficiary.allocated_tokens)
+            })
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // amount may be lower than total_allocated when co-funders top up with fund_escrow,
+        // claims stay closed until the escrow covers every allocation
+        data_account.total_allocated = total_allocated;
+
+        // Strictly ascending keys allow binary search lookups and rule out duplicate wallets
+        let is_sorted = beneficiaries
+            .windows(2)
+            .all(|pair| pair[0].key < pair[1].key);
+
+        if !is_sorted {
+            return Err(ErrorCode::BeneficiariesNotSorted.into());
+        }
+
+        if beneficiaries.len() > constants::MAX_BENEFICIARIES {
+            return Err(ErrorCode::TooManyBeneficiaries.into());
+        }
+
+        // Only the key, the allocation and whether it is revocable come from the caller,
+        // claim, block, withdraw, rotation and revocation state always starts empty
+        for (slot, beneficiary) in data_account.beneficiaries.iter_mut().zip(&beneficiaries) {
+            *slot = Beneficiary {
+                key: beneficiary.key,
+                allocated_tokens: beneficiary.allocated_tokens,
+                revocable: beneficiary.revocable as u8,
+                ..Default::default()
+            };
+        }
+        data_account.beneficiary_count = beneficiaries.len() as u32;
+
+        data_account.released = 0;
         data_account.round = round;
         data_account.claim_type = claim_type;
         data_account.batch = batch;
-        data_account.token_amount = amount;
-        data_account.decimals = decimals; // b/c bpf does not have any floats
+        data_account.decimals = ctx.accounts.token_mint.decimals; // informational only, amounts are in base units
         data_account.initializer = ctx.accounts.sender.to_account_info().key();
         data_account.escrow_wallet = ctx.accounts.escrow_wallet.to_account_info().key();
         data_account.token_mint = ctx.accounts.token_mint.to_account_info().key();
 
-        let transfer_instruction = Transfer {
-            from: ctx.accounts.wallet_to_withdraw_from.to_account_info(),
-            to: ctx.accounts.escrow_wallet.to_account_info(),
-            authority: ctx.accounts.sender.to_account_info(),
-        };
-
-        let cpi_ctx = CpiContext::new(
-            ctx.accounts.token_program.to_account_info(),
-            transfer_instruction,
-        );
-
-        token::transfer(
-            cpi_ctx,
-            data_account.token_amount * u64::pow(10, decimals as u32),
+        // token_amount is what the escrow received, transfer fees are already deducted
+        let received = deposit_to_escrow(
+            &ctx.accounts.token_program,
+            ctx.accounts.wallet_to_withdraw_from.to_account_info(),
+            ctx.accounts.sender.to_account_info(),
+            &mut ctx.accounts.escrow_wallet,
+            &ctx.accounts.token_mint,
+            amount,
         )?;
+        data_account.token_amount = received;
+
+        let clock = Clock::get()?;
+
+        registry.batches.push(BatchEntry {
+            round,
+            claim_type,
+            batch,
+            data_account: data_account_key,
+            token_amount: received,
+            beneficiary_count: data_account.beneficiary_count,
+            created_at: clock.unix_timestamp,
+            is_closed: false,
+        });
+        registry.add_to_round_total(round, received)?;
 
         Ok(())
     }
@@ -66,9 +212,9 @@ pub mod token_claim_program {
         _batch: u8,
         released: bool,
     ) -> Result<()> {
-        let data_account = &mut ctx.accounts.data_account;
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-        data_account.released = released;
+        data_account.set_released(released);
         Ok(())
     }
 
@@ -80,17 +226,17 @@ pub mod token_claim_program {
         user_wallet: Pubkey,
         blocked: bool,
     ) -> Result<()> {
-        let data_account = &mut ctx.accounts.data_account;
-        let beneficiaries = &data_account.beneficiaries;
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-        let (index, _beneficiary) = beneficiaries
-            .iter()
-            .enumerate()
-            .find(|(_, _beneficiary)| _beneficiary.key == user_wallet)
-            .ok_or(ErrorCode::BeneficiaryNotFound)?;
+        let index = data_account.beneficiary_index(&user_wallet)?;
+
+        // tokens of a withdrawn allocation have already left the escrow
+        if !blocked && data_account.beneficiaries[index].is_withdrawn != 0 {
+            return Err(ErrorCode::AllocationWithdrawn.into());
+        }
 
         // beneficiary.is_blocked = blocked;
-        data_account.beneficiaries[index].is_blocked = blocked;
+        data_account.beneficiaries[index].is_blocked = blocked as u8;
 
         Ok(())
     }
@@ -102,298 +248,2105 @@ pub mod token_claim_program {
         _batch: u8,
         blocked: bool,
     ) -> Result<()> {
-        let data_account = &mut ctx.accounts.data_account;
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
         // Iterate through the beneficiaries and update the status
-        for beneficiary in &mut data_account.beneficiaries {
-            if !beneficiary.is_claimed {
-                beneficiary.is_blocked = blocked;
+        for beneficiary in data_account.beneficiaries_mut() {
+            if beneficiary.is_claimed == 0 && beneficiary.is_withdrawn == 0 {
+                beneficiary.is_blocked = blocked as u8;
             }
         }
 
         Ok(())
     }
 
+    pub fn set_rotation_delay(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        rotation_delay: i64,
+    ) -> Result<()> {
+        if rotation_delay < 0 {
+            return Err(ErrorCode::InvalidRotationDelay.into());
+        }
+
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        data_account.rotation_delay = rotation_delay;
 
-    pub fn withdraw_from_escrow(
-        ctx: Context<Withdraw>,
+        Ok(())
+    }
+
+    pub fn rotate_beneficiary(
+        ctx: Context<UpdateUser>,
         _round: u8,
         _claim_type: u8,
         _batch: u8,
+        user_wallet: Pubkey,
+        new_wallet: Pubkey,
     ) -> Result<()> {
-        let escrow_wallet = &mut ctx.accounts.escrow_wallet;
-        let data_account = &mut ctx.accounts.data_account;
-        let beneficiaries = &data_account.beneficiaries;
-        let token_program = &mut ctx.accounts.token_program;
-        let token_mint_key = &mut ctx.accounts.token_mint.key();
-        let admin_ata = &mut ctx.accounts.wallet_to_deposit_to;
-        let decimals = data_account.decimals;
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-        let amount_to_withdraw: u64 = beneficiaries
-            .iter()
-            .filter(|beneficiary| beneficiary.is_blocked) // Filter only blocked beneficiaries
-            .map(|beneficiary| beneficiary.allocated_tokens) // Map to allocated tokens
-            .sum(); // Sum them up
+        let index = data_account.beneficiary_index(&user_wallet)?;
 
-        let bump_for_data = ctx.bumps.escrow_wallet;
+        require!(
+            data_account.beneficiaries[index].pending_key == Pubkey::default(),
+            ErrorCode::RotationPending
+        );
 
-        let data_account_key = data_account.key();
+        // Without a delay the rotation applies right away, otherwise it waits for execute_rotation
+        if data_account.rotation_delay == 0 {
+            return apply_rotation(&mut data_account, index, new_wallet);
+        }
 
-        // Transfer Logic:
-        let seeds: &[&[&[u8]]] = &[&[
-            constants::ESCROW_SEED,
-            token_mint_key.as_ref(),
-            data_account_key.as_ref(),
-            &[bump_for_data],
-        ]];
+        assert_rotatable(&data_account, index, new_wallet)?;
 
-        let transfer_instruction = Transfer {
-            from: escrow_wallet.to_account_info(),
-            to: admin_ata.to_account_info(),
-            authority: escrow_wallet.to_account_info(),
-        };
+        let clock = Clock::get()?;
+        let rotation_eta = clock
+            .unix_timestamp
+            .checked_add(data_account.rotation_delay)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-        let cpi_ctx = CpiContext::new_with_signer(
-            token_program.to_account_info(),
-            transfer_instruction,
-            seeds,
-        );
+        let beneficiary = &mut data_account.beneficiaries[index];
+        beneficiary.pending_key = new_wallet;
+        beneficiary.rotation_eta = rotation_eta;
 
-        token::transfer(cpi_ctx, amount_to_withdraw * u64::pow(10, decimals as u32))?;
+        msg!(
+            "Rotation of {} to {} pending until {}",
+            user_wallet,
+            new_wallet,
+            rotation_eta
+        );
 
         Ok(())
     }
 
-    pub fn claim(ctx: Context<Claim>, _round: u8, _claim_type: u8, _batch: u8) -> Result<()> {
-        let sender = &mut ctx.accounts.sender;
-        let escrow_wallet = &mut ctx.accounts.escrow_wallet;
-        let data_account = &mut ctx.accounts.data_account;
-        let beneficiaries = &data_account.beneficiaries;
-        let token_program = &mut ctx.accounts.token_program;
-        let token_mint_key = &mut ctx.accounts.token_mint.key();
-        let beneficiary_ata = &mut ctx.accounts.wallet_to_deposit_to;
-        let decimals = data_account.decimals;
-
-        let (index, beneficiary) = beneficiaries
-            .iter()
-            .enumerate()
-            .find(|(_, beneficiary)| beneficiary.key == *sender.to_account_info().key)
-            .ok_or(ErrorCode::BeneficiaryNotFound)?;
+    pub fn execute_rotation(
+        ctx: Context<UpdateUser>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        user_wallet: Pubkey,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-        let amount_to_transfer = beneficiary.allocated_tokens;
+        let index = data_account.beneficiary_index(&user_wallet)?;
 
-        require!(beneficiary.in_process == false, ErrorCode::ClaimNotAllowed);
-        require!(beneficiary.is_claimed == false, ErrorCode::ClaimNotAllowed);
-        require!(beneficiary.is_blocked == false, ErrorCode::ClaimNotAllowed);
+        let beneficiary = &data_account.beneficiaries[index];
+        let new_wallet = beneficiary.pending_key;
 
-        data_account.beneficiaries[index].in_process = true;
-        let bump_for_data = ctx.bumps.escrow_wallet;
+        require!(new_wallet != Pubkey::default(), ErrorCode::NoPendingRotation);
 
-        let data_account_key = data_account.key();
+        let clock = Clock::get()?;
+        if clock.unix_timestamp < beneficiary.rotation_eta {
+            return Err(ErrorCode::RotationLocked.into());
+        }
 
-        // Transfer Logic:
-        let seeds: &[&[&[u8]]] = &[&[
-            constants::ESCROW_SEED,
-            token_mint_key.as_ref(),
-            data_account_key.as_ref(),
-            &[bump_for_data],
-        ]];
+        apply_rotation(&mut data_account, index, new_wallet)
+    }
 
-        let transfer_instruction = Transfer {
-            from: escrow_wallet.to_account_info(),
-            to: beneficiary_ata.to_account_info(),
-            authority: escrow_wallet.to_account_info(),
-        };
+    pub fn cancel_rotation(
+        ctx: Context<RotateBeneficiary>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        user_wallet: Pubkey,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let sender = ctx.accounts.sender.key();
 
-        let cpi_ctx = CpiContext::new_with_signer(
-            token_program.to_account_info(),
-            transfer_instruction,
-            seeds,
+        // An operator and the current wallet can both stop a pending rotation
+        if !data_account.is_operator(&sender) && sender != user_wallet {
+            return Err(ErrorCode::InvalidSender.into());
+        }
+
+        let index = data_account.beneficiary_index(&user_wallet)?;
+        let beneficiary = &mut data_account.beneficiaries[index];
+
+        require!(
+            beneficiary.pending_key != Pubkey::default(),
+            ErrorCode::NoPendingRotation
         );
 
-        token::transfer(cpi_ctx, amount_to_transfer * u64::pow(10, decimals as u32))?;
-        data_account.beneficiaries[index].is_claimed = true;
-        data_account.beneficiaries[index].in_process = false;
+        beneficiary.pending_key = Pubkey::default();
+        beneficiary.rotation_eta = 0;
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-#[instruction(round: u8 , claim_type: u8, batch: u8,list_size: u64)]
-pub struct Initialize<'info> {
-    #[account(
-        init,
-        payer = sender,
-        space = 8 + 1 + 1 + 1 + 1 + 8 + 32 + 32 + 32 + 1 + (4 +( list_size as usize * (32 + 8 + 1 + 1 + 1)) + 1), // define the size
-        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()], 
-        bump 
-    )]
-    pub data_account: Account<'info, DataAccount>,
+    pub fn self_rotate_beneficiary(
+        ctx: Context<RotateBeneficiary>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        new_wallet: Pubkey,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let sender = ctx.accounts.sender.key();
 
-    #[account(
-        init,
-        payer = sender,
-        seeds=[constants::ESCROW_SEED.as_ref(), token_mint.key().as_ref(), data_account.key().as_ref()],
-        bump,
-        token::mint=token_mint,
-        token::authority=escrow_wallet,
-    )]
-    pub escrow_wallet: Account<'info, TokenAccount>,
+        // Signed by the old key, so no timelock is needed
+        let index = data_account.beneficiary_index(&sender)?;
 
-    #[account(
-        mut,
-        constraint=wallet_to_withdraw_from.owner == sender.key(),
-        constraint=wallet_to_withdraw_from.mint == token_mint.key()
-    )]
-    pub wallet_to_withdraw_from: Account<'info, TokenAccount>,
+        require!(
+            data_account.beneficiaries[index].pending_key == Pubkey::default(),
+            ErrorCode::RotationPending
+        );
 
-    pub token_mint: Account<'info, Mint>,
+        apply_rotation(&mut data_account, index, new_wallet)
+    }
 
-    #[account(mut)]
-    pub sender: Signer<'info>,
 
-    pub system_program: Program<'info, System>,
+    pub fn withdraw_from_escrow(
+        ctx: Context<Withdraw>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let beneficiaries = data_account.beneficiaries();
 
-    pub token_program: Program<'info, Token>,
-}
+        // Only the unclaimed part of blocked allocations still sitting in the escrow can be withdrawn
+        let amount_to_withdraw: u64 = beneficiaries
+            .iter()
+            .filter(|beneficiary| beneficiary.is_withdrawable())
+            .try_fold(0u64, |total, beneficiary| {
+                total.checked_add(beneficiary.unclaimed_tokens())
+            })
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        if amount_to_withdraw == 0 {
+            return Err(ErrorCode::NothingToWithdraw.into());
+        }
 
-#[derive(Accounts)]
-#[instruction(round: u8 , claim_type: u8, batch: u8)]
-pub struct Release<'info> {
-    #[account(
-        mut,
-        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
-        bump,
-        constraint=data_account.initializer == sender.key() @ ErrorCode::InvalidSender
-    )]
-    pub data_account: Account<'info, DataAccount>,
+        // Co-funders own part of these tokens, their share stays in the escrow for refund_funder
+        let owner_amount = data_account.owner_share(amount_to_withdraw)?;
+
+        if owner_amount > 0 {
+            transfer_from_escrow(
+                &ctx.accounts.token_program,
+                ctx.accounts.escrow_wallet.to_account_info(),
+                ctx.accounts.wallet_to_deposit_to.to_account_info(),
+                &ctx.accounts.token_mint,
+                &data_account_key,
+                ctx.bumps.escrow_wallet,
+                owner_amount,
+            )?;
+        }
 
-    pub token_mint: Account<'info, Mint>,
+        // Mark the allocations as withdrawn so they can not be withdrawn or claimed again
+        for beneficiary in data_account.beneficiaries_mut() {
+            if beneficiary.is_withdrawable() {
+                beneficiary.is_withdrawn = 1;
+            }
+        }
+        data_account.total_withdrawn = data_account
+            .total_withdrawn
+            .checked_add(amount_to_withdraw)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-    #[account(mut)]
-    pub sender: Signer<'info>,
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    pub fn set_claim_deadline(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        claim_deadline: Option<i64>,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-#[derive(Accounts)]
-#[instruction(round: u8 , claim_type: u8, batch: u8)]
-pub struct UpdateUser<'info> {
-    #[account(
-        mut,
-        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
-        bump,
-        constraint=data_account.initializer == sender.key() @ ErrorCode::InvalidSender
-    )]
-    pub data_account: Account<'info, DataAccount>,
+        if let Some(deadline) = claim_deadline {
+            let clock = Clock::get()?;
+            if deadline <= clock.unix_timestamp {
+                return Err(ErrorCode::InvalidClaimDeadline.into());
+            }
+        }
 
-    pub token_mint: Account<'info, Mint>,
+        data_account.claim_deadline = claim_deadline.unwrap_or(0);
 
-    #[account(mut)]
-    pub sender: Signer<'info>,
+        Ok(())
+    }
 
-    pub system_program: Program<'info, System>,
-}
+    pub fn sweep_expired(
+        ctx: Context<Withdraw>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-#[derive(Accounts)]
-#[instruction(round: u8 , claim_type: u8, batch: u8)]
-pub struct Claim<'info> {
-    #[account(
-        mut,
-        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
-        bump,
-    )]
-    pub data_account: Account<'info, DataAccount>,
+        let deadline = data_account
+            .claim_deadline()
+            .ok_or(ErrorCode::ClaimDeadlineNotReached)?;
 
-    #[account(
-        mut,
-       seeds=[constants::ESCROW_SEED.as_ref(), token_mint.key().as_ref(), data_account.key().as_ref()],
-        bump,
-    )]
-    pub escrow_wallet: Account<'info, TokenAccount>,
+        let clock = Clock::get()?;
+        if clock.unix_timestamp <= deadline {
+            return Err(ErrorCode::ClaimDeadlineNotReached.into());
+        }
 
-    #[account(mut)]
-    pub sender: Signer<'info>,
+        // Every allocation still in the escrow expired, blocked or not
+        let amount_to_withdraw: u64 = data_account
+            .beneficiaries()
+            .iter()
+            .filter(|beneficiary| beneficiary.is_unsettled())
+            .try_fold(0u64, |total, beneficiary| {
+                total.checked_add(beneficiary.unclaimed_tokens())
+            })
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        if amount_to_withdraw == 0 {
+            return Err(ErrorCode::NothingToWithdraw.into());
+        }
 
-    pub token_mint: Account<'info, Mint>,
+        // Co-funders own part of these tokens, their share stays in the escrow for refund_funder
+        let owner_amount = data_account.owner_share(amount_to_withdraw)?;
+
+        if owner_amount > 0 {
+            transfer_from_escrow(
+                &ctx.accounts.token_program,
+                ctx.accounts.escrow_wallet.to_account_info(),
+                ctx.accounts.wallet_to_deposit_to.to_account_info(),
+                &ctx.accounts.token_mint,
+                &data_account_key,
+                ctx.bumps.escrow_wallet,
+                owner_amount,
+            )?;
+        }
 
-    #[account(
-        init_if_needed,
-        payer = sender,
-        associated_token::mint = token_mint,
-        associated_token::authority = sender,
-    )]
-    pub wallet_to_deposit_to: Account<'info, TokenAccount>,
+        // is_blocked is left untouched, is_withdrawn alone keeps the allocation from being claimed
+        for beneficiary in data_account.beneficiaries_mut() {
+            if beneficiary.is_unsettled() {
+                beneficiary.is_withdrawn = 1;
+            }
+        }
+        data_account.total_withdrawn = data_account
+            .total_withdrawn
+            .checked_add(amount_to_withdraw)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-    pub associated_token_program: Program<'info, AssociatedToken>,
+        Ok(())
+    }
 
-    pub token_program: Program<'info, Token>,
+    pub fn set_vesting_schedule(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        vesting_start: i64,
+        vesting_end: i64,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
 
-    pub system_program: Program<'info, System>,
-}
+        // Both values at 0 turn vesting off, the whole allocation is claimable at once
+        let is_disabled = vesting_start == 0 && vesting_end == 0;
 
-#[derive(Accounts)]
-#[instruction(round: u8 , claim_type: u8, batch: u8)]
-pub struct Withdraw<'info> {
-    #[account(
-        mut,
-        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
-        bump,
-        constraint=data_account.initializer == sender.key() @ ErrorCode::InvalidSender
-    )]
-    pub data_account: Account<'info, DataAccount>,
+        if !is_disabled && vesting_end <= vesting_start {
+            return Err(ErrorCode::InvalidVestingSchedule.into());
+        }
 
-    #[account(
-        mut,
-       seeds=[constants::ESCROW_SEED.as_ref(), token_mint.key().as_ref(), data_account.key().as_ref()],
-        bump,
-    )]
-    pub escrow_wallet: Account<'info, TokenAccount>,
+        if data_account.vesting_schedule_locked() {
+            return Err(ErrorCode::VestingScheduleLocked.into());
+        }
 
-    #[account(mut)]
-    pub sender: Signer<'info>,
+        data_account.vesting_start = vesting_start;
+        data_account.vesting_end = vesting_end;
 
-    pub token_mint: Account<'info, Mint>,
+        Ok(())
+    }
 
-    #[account(
-        init_if_needed,
-        payer = sender,
-        associated_token::mint = token_mint,
-        associated_token::authority = sender,
-    )]
-    pub wallet_to_deposit_to: Account<'info, TokenAccount>,
+    pub fn revoke(
+        ctx: Context<Revoke>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        user_wallet: Pubkey,
+    ) -> Result<()> {
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let clock = Clock::get()?;
+
+        let index = data_account.beneficiary_index(&user_wallet)?;
+
+        let beneficiary = &data_account.beneficiaries[index];
+
+        require!(beneficiary.revocable != 0, ErrorCode::NotRevocable);
+        require!(beneficiary.is_revoked == 0, ErrorCode::AlreadyRevoked);
+        require!(beneficiary.in_process == 0, ErrorCode::RevokeNotAllowed);
+        require!(beneficiary.is_claimed == 0, ErrorCode::RevokeNotAllowed);
+        require!(beneficiary.is_withdrawn == 0, ErrorCode::RevokeNotAllowed);
+
+        // Vesting freezes here, the beneficiary keeps what has vested so far
+        let vested_tokens = data_account.vested_tokens(beneficiary, clock.unix_timestamp)?;
+        let unvested_tokens = beneficiary
+            .allocated_tokens
+            .checked_sub(vested_tokens)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let beneficiary = &mut data_account.beneficiaries[index];
+        beneficiary.allocated_tokens = vested_tokens;
+        beneficiary.is_revoked = 1;
+        beneficiary.is_claimed = (beneficiary.claimed_tokens == beneficiary.allocated_tokens) as u8;
+
+        // Like withdrawals, only the owner's share leaves the escrow, co-funders get theirs back in refund_funder
+        let owner_amount = data_account.owner_share(unvested_tokens)?;
+
+        if owner_amount > 0 {
+            transfer_from_escrow(
+                &ctx.accounts.token_program,
+                ctx.accounts.escrow_wallet.to_account_info(),
+                ctx.accounts.destination.to_account_info(),
+                &ctx.accounts.token_mint,
+                &data_account_key,
+                ctx.bumps.escrow_wallet,
+                owner_amount,
+            )?;
+        }
 
-    pub associated_token_program: Program<'info, AssociatedToken>,
+        data_account.total_withdrawn = data_account
+            .total_withdrawn
+            .checked_add(unvested_tokens)
+            .ok_or(ErrorCode::MathOverflow)?;
 
-    pub token_program: Program<'info, Token>,
+        msg!(
+            "Revoked {}: {} vested, {} returned",
+            user_wallet,
+            vested_tokens,
+            owner_amount
+        );
 
-    pub system_program: Program<'info, System>,
+        Ok(())
+    }
+
+    pub fn set_compliance_signer(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        compliance_signer: Option<Pubkey>,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        data_account.compliance_signer = compliance_signer.unwrap_or_default();
+
+        Ok(())
+    }
+
+    pub fn grant_role(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        role: Role,
+        key: Pubkey,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+
+        match role {
+            Role::Operator => data_account.operator = key,
+            Role::Treasurer => data_account.treasurer = key,
+        }
+
+        msg!("Granted {:?} to {}", role, key);
+
+        Ok(())
+    }
+
+    pub fn revoke_role(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        role: Role,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+
+        match role {
+            Role::Operator => data_account.operator = Pubkey::default(),
+            Role::Treasurer => data_account.treasurer = Pubkey::default(),
+        }
+
+        msg!("Revoked {:?}", role);
+
+        Ok(())
+    }
+
+    pub fn transfer_ownership(
+        ctx: Context<UpdatePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+
+        // The new owner has to accept, so a typo can not lock the pool
+        data_account.pending_owner = new_owner;
+
+        Ok(())
+    }
+
+    pub fn accept_ownership(
+        ctx: Context<AcceptOwnership>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+
+        msg!(
+            "Ownership moved from {} to {}",
+            data_account.initializer,
+            data_account.pending_owner
+        );
+
+        data_account.initializer = data_account.pending_owner;
+        data_account.pending_owner = Pubkey::default();
+
+        Ok(())
+    }
+
+    pub fn fund_escrow(
+        ctx: Context<FundEscrow>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        amount: u64,
+    ) -> Result<()> {
+        if amount == 0 {
+            return Err(ErrorCode::InvalidAmount.into());
+        }
+
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let funder_account = &mut ctx.accounts.funder_account;
+
+        // Funders are credited with what the escrow received, not what they sent
+        let received = deposit_to_escrow(
+            &ctx.accounts.token_program,
+            ctx.accounts.funder_token_account.to_account_info(),
+            ctx.accounts.funder.to_account_info(),
+            &mut ctx.accounts.escrow_wallet,
+            &ctx.accounts.token_mint,
+            amount,
+        )?;
+
+        // first deposit of this funder
+        if funder_account.funder == Pubkey::default() {
+            funder_account.data_account = data_account_key;
+            funder_account.funder = ctx.accounts.funder.key();
+            data_account.open_funders += 1;
+        }
+
+        funder_account.amount_funded = funder_account
+            .amount_funded
+            .checked_add(received)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        data_account.token_amount = data_account
+            .token_amount
+            .checked_add(received)
+            .ok_or(ErrorCode::MathOverflow)?;
+        data_account.total_funded = data_account
+            .total_funded
+            .checked_add(received)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let registry = &mut ctx.accounts.registry;
+
+        if let Some(entry) = registry
+            .batches
+            .iter_mut()
+            .find(|entry| entry.data_account == data_account_key)
+        {
+            entry.token_amount = data_account.token_amount;
+        }
+        registry.add_to_round_total(data_account.round, received)?;
+
+        Ok(())
+    }
+
+    pub fn refund_funder(
+        ctx: Context<RefundFunder>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let data_account_key = ctx.accounts.data_account.key();
+        let mut data_account = ctx.accounts.data_account.load_mut()?;
+        let funder_account = &ctx.accounts.funder_account;
+
+        if !data_account.is_finished() {
+            return Err(ErrorCode::PoolNotFinished.into());
+        }
+
+        // Every funder gets their share of the tokens that were not claimed, so the order
+        // of the refunds does not matter, rounding dust goes to the owner in close_pool
+        let amount_to_refund = data_account
+            .funder_refund(funder_account.amount_funded)?
+            .min(ctx.accounts.escrow_wallet.amount);
+
+        if amount_to_refund > 0 {
+            transfer_from_escrow(
+                &ctx.accounts.token_program,
+                ctx.accounts.escrow_wallet.to_account_info(),
+                ctx.accounts.funder_token_account.to_account_info(),
+                &ctx.accounts.token_mint,
+                &data_account_key,
+                ctx.bumps.escrow_wallet,
+                amount_to_refund,
+            )?;
+        }
+
+        msg!(
+            "Refunded {} of {} to {}",
+            amount_to_refund,
+            funder_account.amount_funded,
+            funder_account.funder
+        );
+
+        // funder_account is closed to the funder by the `close` constraint
+        data_account.open_funders -= 1;
+
+        Ok(())
+    }
+
+    pub fn close_pool(
+        ctx: Context<ClosePool>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let data_account_key = ctx.accounts.data_account.key();
+        let data_account = ctx.accounts.data_account.load()?;
+
+        if !data_account.is_finished() {
+            return Err(ErrorCode::PoolNotFinished.into());
+        }
+
+        // Co-funders are refunded before the owner sweeps the rest
+        if data_account.open_funders > 0 {
+            return Err(ErrorCode::FundersNotRefunded.into());
+        }
+
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let bump_for_escrow = ctx.bumps.escrow_wallet;
+
+        // Sweep whatever is left over (rounding dust, over-funding) to the initializer
+        let dust = ctx.accounts.escrow_wallet.amount;
+        if dust > 0 {
+            transfer_from_escrow(
+                &ctx.accounts.token_program,
+                ctx.accounts.escrow_wallet.to_account_info(),
+                ctx.accounts.wallet_to_deposit_to.to_account_info(),
+                &ctx.accounts.token_mint,
+                &data_account_key,
+                bump_for_escrow,
+                dust,
+            )?;
+        }
+
+        // Token-2022 refuses to close an account that still holds withheld transfer fees,
+        // moving them to the mint is permissionless
+        if has_transfer_fee(&ctx.accounts.token_mint.to_account_info())? {
+            let harvest_instruction = HarvestWithheldTokensToMint {
+                token_program_id: ctx.accounts.token_program.to_account_info(),
+                mint: ctx.accounts.token_mint.to_account_info(),
+            };
+
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                harvest_instruction,
+            );
+
+            harvest_withheld_tokens_to_mint(
+                cpi_ctx,
+                vec![ctx.accounts.escrow_wallet.to_account_info()],
+            )?;
+        }
+
+        let seeds: &[&[&[u8]]] = &[&[
+            constants::ESCROW_SEED,
+            token_mint_key.as_ref(),
+            data_account_key.as_ref(),
+            &[bump_for_escrow],
+        ]];
+
+        let close_instruction = CloseAccount {
+            account: ctx.accounts.escrow_wallet.to_account_info(),
+            destination: ctx.accounts.sender.to_account_info(),
+            authority: ctx.accounts.escrow_wallet.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            close_instruction,
+            seeds,
+        );
+
+        // data_account itself is closed to the sender by the `close` constraint
+        token_interface::close_account(cpi_ctx)?;
+
+        if let Some(entry) = ctx
+            .accounts
+            .registry
+            .batches
+            .iter_mut()
+            .find(|entry| entry.data_account == data_account_key)
+        {
+            entry.is_closed = true;
+        }
+
+        Ok(())
+    }
+
+    pub fn claim(ctx: Context<Claim>, _round: u8, _claim_type: u8, _batch: u8) -> Result<()> {
+        let wallet = ctx.accounts.sender.key();
+
+        // Claims everything that is left of the allocation to the signer's own ATA
+        claim_tokens(
+            &ctx.accounts.data_account,
+            &ctx.accounts.escrow_wallet,
+            &ctx.accounts.token_mint,
+            ctx.accounts.wallet_to_deposit_to.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.bumps.escrow_wallet,
+            ClaimRequest {
+                wallet,
+                amount: None,
+                instructions: Some(&ctx.accounts.instructions.to_account_info()),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    pub fn claim_to(
+        ctx: Context<ClaimTo>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+        amount: u64,
+    ) -> Result<()> {
+        let wallet = ctx.accounts.sender.key();
+
+        // The beneficiary signs, the tokens go to any token account of the same mint
+        claim_tokens(
+            &ctx.accounts.data_account,
+            &ctx.accounts.escrow_wallet,
+            &ctx.accounts.token_mint,
+            ctx.accounts.destination.to_account_info(),
+            &ctx.accounts.token_program,
+            ctx.bumps.escrow_wallet,
+            ClaimRequest {
+                wallet,
+                amount: Some(amount),
+                instructions: Some(&ctx.accounts.instructions.to_account_info()),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// remaining_accounts: `[data_account, escrow_wallet]` pairs of the same mint
+    pub fn claim_many<'info>(ctx: Context<'_, '_, 'info, 'info, ClaimMany<'info>>) -> Result<()> {
+        let wallet = ctx.accounts.sender.key();
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let remaining_accounts = ctx.remaining_accounts;
+
+        if remaining_accounts.is_empty() || !remaining_accounts.len().is_multiple_of(2) {
+            return Err(ErrorCode::InvalidRemainingAccounts.into());
+        }
+
+        let mut total_claimed: u64 = 0;
+
+        for pair in remaining_accounts.chunks(2) {
+            let data_account = AccountLoader::<DataAccount>::try_from(&pair[0])?;
+            let (round, claim_type, batch) = {
+                let data = data_account.load()?;
+                (data.round, data.claim_type, data.batch)
+            };
+
+            // The data account must be the PDA of its own round, claim type and batch
+            let (expected_data_account, _) = Pubkey::find_program_address(
+                &[
+                    &round.to_le_bytes(),
+                    &claim_type.to_le_bytes(),
+                    &batch.to_le_bytes(),
+                    constants::DATA_SEED,
+                    token_mint_key.as_ref(),
+                ],
+                ctx.program_id,
+            );
+            if expected_data_account != data_account.key() {
+                return Err(ErrorCode::InvalidRemainingAccounts.into());
+            }
+
+            let (expected_escrow, bump_for_escrow) = Pubkey::find_program_address(
+                &[
+                    constants::ESCROW_SEED,
+                    token_mint_key.as_ref(),
+                    data_account.key().as_ref(),
+                ],
+                ctx.program_id,
+            );
+            if expected_escrow != pair[1].key() {
+                return Err(ErrorCode::InvalidRemainingAccounts.into());
+            }
+            let escrow_wallet = InterfaceAccount::<TokenAccount>::try_from(&pair[1])?;
+
+            // Ineligible batches (blocked, claimed, expired, nothing vested, compliance gated, ...)
+            // are skipped, every other error aborts the whole claim
+            let claimed = match claim_tokens(
+                &data_account,
+                &escrow_wallet,
+                &ctx.accounts.token_mint,
+                ctx.accounts.wallet_to_deposit_to.to_account_info(),
+                &ctx.accounts.token_program,
+                bump_for_escrow,
+                ClaimRequest {
+                    wallet,
+                    amount: None,
+                    instructions: None,
+                },
+            ) {
+                Ok(claimed) => claimed,
+                Err(error) if is_ineligible_claim(&error) => {
+                    msg!("Skipping {}", data_account.key());
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            total_claimed = total_claimed
+                .checked_add(claimed)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        if total_claimed == 0 {
+            return Err(ErrorCode::NothingToClaim.into());
+        }
+
+        msg!("Claimed {} from multiple batches", total_claimed);
+
+        Ok(())
+    }
+
+    /// remaining_accounts: beneficiaries' associated token accounts for `token_mint`
+    pub fn distribute<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Distribute<'info>>,
+        _round: u8,
+        _claim_type: u8,
+        _batch: u8,
+    ) -> Result<()> {
+        let token_mint_key = ctx.accounts.token_mint.key();
+        let bump_for_escrow = ctx.bumps.escrow_wallet;
+
+        let mut total_distributed: u64 = 0;
+
+        for account_info in ctx.remaining_accounts.iter() {
+            let beneficiary_ata = InterfaceAccount::<TokenAccount>::try_from(account_info)?;
+
+            // Only the canonical ATA of the stored beneficiary key can receive the allocation
+            if beneficiary_ata.mint != token_mint_key
+                || beneficiary_ata.key()
+                    != get_associated_token_address_with_program_id(
+                        &beneficiary_ata.owner,
+                        &token_mint_key,
+                        &ctx.accounts.token_program.key(),
+                    )
+            {
+                return Err(ErrorCode::InvalidDestination.into());
+            }
+
+            // Already paid, blocked or otherwise ineligible beneficiaries are skipped,
+            // which makes it safe to resume the distribution across many transactions.
+            // Every other error aborts the distribution
+            match claim_tokens(
+                &ctx.accounts.data_account,
+                &ctx.accounts.escrow_wallet,
+                &ctx.accounts.token_mint,
+                account_info.clone(),
+                &ctx.accounts.token_program,
+                bump_for_escrow,
+                ClaimRequest {
+                    wallet: beneficiary_ata.owner,
+                    amount: None,
+                    instructions: None,
+                },
+            ) {
+                Ok(distributed) => {
+                    total_distributed = total_distributed
+                        .checked_add(distributed)
+                        .ok_or(ErrorCode::MathOverflow)?;
+                }
+                Err(error) if is_ineligible_claim(&error) => {
+                    msg!("Skipping {}", beneficiary_ata.owner)
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        msg!("Distributed {}", total_distributed);
+
+        Ok(())
+    }
+}
+
+/// Checks that the allocation at `index` can be moved to `new_wallet`
+fn assert_rotatable(data_account: &DataAccount, index: usize, new_wallet: Pubkey) -> Result<()> {
+    let beneficiary = &data_account.beneficiaries[index];
+
+    require!(beneficiary.in_process == 0, ErrorCode::RotationNotAllowed);
+    require!(beneficiary.is_claimed == 0, ErrorCode::RotationNotAllowed);
+    require!(beneficiary.is_withdrawn == 0, ErrorCode::RotationNotAllowed);
+    require!(new_wallet != Pubkey::default(), ErrorCode::InvalidNewWallet);
+
+    // A wallet can only appear once per batch, otherwise lookups by key become ambiguous
+    if data_account.beneficiary_index(&new_wallet).is_ok() {
+        return Err(ErrorCode::BeneficiaryExists.into());
+    }
+
+    Ok(())
+}
+
+fn apply_rotation(data_account: &mut DataAccount, index: usize, new_wallet: Pubkey) -> Result<()> {
+    assert_rotatable(data_account, index, new_wallet)?;
+
+    let beneficiaries = data_account.beneficiaries_mut();
+
+    let beneficiary = &mut beneficiaries[index];
+    msg!("Rotated beneficiary {} to {}", beneficiary.key, new_wallet);
+
+    beneficiary.key = new_wallet;
+    beneficiary.pending_key = Pubkey::default();
+    beneficiary.rotation_eta = 0;
+
+    // Move the record to the position of the new key to keep the list sorted
+    let smaller_before = beneficiaries[..index].partition_point(|other| other.key < new_wallet);
+    let new_index = if smaller_before < index {
+        smaller_before
+    } else {
+        index + beneficiaries[index + 1..].partition_point(|other| other.key < new_wallet)
+    };
+
+    if new_index < index {
+        beneficiaries[new_index..=index].rotate_right(1);
+    } else {
+        beneficiaries[index..=new_index].rotate_left(1);
+    }
+
+    Ok(())
+}
+
+/// `amount * part / total`, rounded down
+fn pro_rata(amount: u64, part: u64, total: u64) -> Result<u64> {
+    if total == 0 {
+        return Ok(0);
+    }
+
+    let share = (amount as u128)
+        .checked_mul(part as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / total as u128;
+
+    u64::try_from(share).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+fn transfer_from_escrow<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    escrow_wallet: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    data_account_key: &Pubkey,
+    bump_for_escrow: u8,
+    amount: u64,
+) -> Result<()> {
+    let token_mint_key = token_mint.key();
+    let seeds: &[&[&[u8]]] = &[&[
+        constants::ESCROW_SEED,
+        token_mint_key.as_ref(),
+        data_account_key.as_ref(),
+        &[bump_for_escrow],
+    ]];
+
+    let transfer_instruction = TransferChecked {
+        from: escrow_wallet.clone(),
+        mint: token_mint.to_account_info(),
+        to,
+        authority: escrow_wallet,
+    };
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        transfer_instruction,
+        seeds,
+    );
+
+    token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)
+}
+
+/// Transfers `amount` from a signer's token account into an escrow and returns how much
+/// the escrow actually received, which is less than `amount` for transfer fee mints
+fn deposit_to_escrow<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    from: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    escrow_wallet: &mut InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    amount: u64,
+) -> Result<u64> {
+    let balance_before = escrow_wallet.amount;
+
+    let transfer_instruction = TransferChecked {
+        from,
+        mint: token_mint.to_account_info(),
+        to: escrow_wallet.to_account_info(),
+        authority,
+    };
+
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), transfer_instruction);
+
+    token_interface::transfer_checked(cpi_ctx, amount, token_mint.decimals)?;
+
+    escrow_wallet.reload()?;
+
+    let received = escrow_wallet
+        .amount
+        .checked_sub(balance_before)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(received)
+}
+
+/// Allow-list of Token-2022 extensions for the claim mint, anything that could take
+/// tokens out of the escrow or lock them there is rejected
+fn assert_supported_mint(token_mint: &AccountInfo) -> Result<()> {
+    if *token_mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let mint_data = token_mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    for extension in mint_state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata
+            | ExtensionType::InterestBearingConfig => {}
+            _ => return Err(ErrorCode::UnsupportedMintExtension.into()),
+        }
+    }
+
+    Ok(())
+}
+
+fn has_transfer_fee(token_mint: &AccountInfo) -> Result<bool> {
+    if *token_mint.owner != spl_token_2022::ID {
+        return Ok(false);
+    }
+
+    let mint_data = token_mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    Ok(mint_state
+        .get_extension_types()?
+        .contains(&ExtensionType::TransferFeeConfig))
+}
+
+/// Checks that the instruction right before this one is an Ed25519 signature by
+/// `compliance_signer` over `pool || wallet || expiry` (expiry as i64 little endian)
+fn verify_compliance_attestation(
+    instructions: &AccountInfo,
+    compliance_signer: &Pubkey,
+    pool: &Pubkey,
+    wallet: &Pubkey,
+    now: i64,
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)?;
+    if current_index == 0 {
+        return Err(ErrorCode::MissingAttestation.into());
+    }
+
+    let ed25519_instruction =
+        load_instruction_at_checked((current_index - 1) as usize, instructions)?;
+
+    if ed25519_instruction.program_id != ed25519_program::ID {
+        return Err(ErrorCode::MissingAttestation.into());
+    }
+
+    verify_attestation_data(
+        &ed25519_instruction.data,
+        compliance_signer,
+        pool,
+        wallet,
+        now,
+    )
+}
+
+/// Checks the data of the Ed25519 instruction, the signature itself is verified by the Ed25519 program
+fn verify_attestation_data(
+    data: &[u8],
+    compliance_signer: &Pubkey,
+    pool: &Pubkey,
+    wallet: &Pubkey,
+    now: i64,
+) -> Result<()> {
+    // Layout: [num_signatures: u8, padding: u8, 7 x u16 offsets, ...data]
+    if data.len() < 16 || data[0] != 1 {
+        return Err(ErrorCode::InvalidAttestation.into());
+    }
+
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+
+    let signature_instruction_index = read_u16(4);
+    let public_key_offset = read_u16(6) as usize;
+    let public_key_instruction_index = read_u16(8);
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    let message_instruction_index = read_u16(14);
+
+    // Signature, key and message must all live in the Ed25519 instruction itself
+    if signature_instruction_index != u16::MAX
+        || public_key_instruction_index != u16::MAX
+        || message_instruction_index != u16::MAX
+        || message_size != 32 + 32 + 8
+    {
+        return Err(ErrorCode::InvalidAttestation.into());
+    }
+
+    let public_key = data
+        .get(public_key_offset..public_key_offset + 32)
+        .ok_or(ErrorCode::InvalidAttestation)?;
+    let message = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(ErrorCode::InvalidAttestation)?;
+
+    if public_key != compliance_signer.as_ref()
+        || &message[..32] != pool.as_ref()
+        || &message[32..64] != wallet.as_ref()
+    {
+        return Err(ErrorCode::InvalidAttestation.into());
+    }
+
+    let expiry = i64::from_le_bytes(message[64..72].try_into().unwrap());
+    if now > expiry {
+        return Err(ErrorCode::AttestationExpired.into());
+    }
+
+    Ok(())
+}
+
+/// Beneficiary side of a claim
+struct ClaimRequest<'a, 'info> {
+    wallet: Pubkey,
+    amount: Option<u64>,                          // everything that has vested when `None`
+    instructions: Option<&'a AccountInfo<'info>>, // only needed in compliance gated pools
+}
+
+/// Errors of claim_tokens that only mean the wallet can not claim from the batch right now. All of
+/// them are raised before the transfer
+const INELIGIBLE_CLAIM_ERRORS: [ErrorCode; 9] = [
+    ErrorCode::BeneficiaryNotFound,
+    ErrorCode::ClaimNotAllowed,
+    ErrorCode::RotationPending,
+    ErrorCode::AllocationExceedsAmount,
+    ErrorCode::ClaimDeadlinePassed,
+    ErrorCode::MissingAttestation,
+    ErrorCode::InvalidAttestation,
+    ErrorCode::AttestationExpired,
+    ErrorCode::NothingVested,
+];
+
+/// Batch claims skip ineligible wallets, anything else (overflow, bad accounts, failed
+/// transfers) has to abort them
+fn is_ineligible_claim(error: &Error) -> bool {
+    INELIGIBLE_CLAIM_ERRORS
+        .iter()
+        .any(|code| *error == Error::from(*code))
+}
+
+/// Transfers `request.amount` of `request.wallet`'s allocation out of the escrow
+fn claim_tokens<'info>(
+    data_account: &AccountLoader<'info, DataAccount>,
+    escrow_wallet: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    destination: AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    bump_for_escrow: u8,
+    request: ClaimRequest<'_, 'info>,
+) -> Result<u64> {
+    let ClaimRequest {
+        wallet,
+        amount,
+        instructions,
+    } = request;
+
+    let data_account_key = data_account.key();
+    let mut data_account = data_account.load_mut()?;
+
+    let index = data_account.beneficiary_index(&wallet)?;
+    let beneficiary = &data_account.beneficiaries[index];
+
+    require!(beneficiary.in_process == 0, ErrorCode::ClaimNotAllowed);
+    require!(beneficiary.is_claimed == 0, ErrorCode::ClaimNotAllowed);
+    require!(beneficiary.is_blocked == 0, ErrorCode::ClaimNotAllowed);
+    require!(beneficiary.is_withdrawn == 0, ErrorCode::ClaimNotAllowed);
+    require!(
+        beneficiary.pending_key == Pubkey::default(),
+        ErrorCode::RotationPending
+    );
+
+    require!(
+        data_account.token_amount >= data_account.total_allocated,
+        ErrorCode::AllocationExceedsAmount
+    );
+
+    let clock = Clock::get()?;
+
+    if let Some(deadline) = data_account.claim_deadline() {
+        if clock.unix_timestamp > deadline {
+            return Err(ErrorCode::ClaimDeadlinePassed.into());
+        }
+    }
+
+    // Pools gated by a compliance signer need a fresh backend attestation for this wallet
+    if let Some(compliance_signer) = data_account.compliance_signer() {
+        let instructions = instructions.ok_or(ErrorCode::MissingAttestation)?;
+        verify_compliance_attestation(
+            instructions,
+            &compliance_signer,
+            &data_account_key,
+            &wallet,
+            clock.unix_timestamp,
+        )?;
+    }
+
+    // Only the vested part of the allocation can be claimed
+    let claimable_tokens = data_account
+        .vested_tokens(beneficiary, clock.unix_timestamp)?
+        .saturating_sub(beneficiary.claimed_tokens);
+
+    require!(claimable_tokens > 0, ErrorCode::NothingVested);
+
+    let amount_to_transfer = amount.unwrap_or(claimable_tokens);
+
+    require!(amount_to_transfer > 0, ErrorCode::InvalidAmount);
+    require!(
+        amount_to_transfer <= claimable_tokens,
+        ErrorCode::AmountExceedsAllocation
+    );
+
+    data_account.beneficiaries[index].in_process = 1;
+
+    transfer_from_escrow(
+        token_program,
+        escrow_wallet.to_account_info(),
+        destination,
+        token_mint,
+        &data_account_key,
+        bump_for_escrow,
+        amount_to_transfer,
+    )?;
+
+    let beneficiary = &mut data_account.beneficiaries[index];
+    beneficiary.claimed_tokens = beneficiary
+        .claimed_tokens
+        .checked_add(amount_to_transfer)
+        .ok_or(ErrorCode::MathOverflow)?;
+    beneficiary.is_claimed = (beneficiary.claimed_tokens == beneficiary.allocated_tokens) as u8;
+    beneficiary.in_process = 0;
+
+    data_account.total_claimed = data_account
+        .total_claimed
+        .checked_add(amount_to_transfer)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(amount_to_transfer)
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        payer = sender,
+        space = 8 + std::mem::size_of::<DataAccount>(), // fixed size, the beneficiary array holds MAX_BENEFICIARIES slots
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()], 
+        bump 
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        init,
+        payer = sender,
+        seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+        token::mint=token_mint,
+        token::authority=escrow_wallet,
+        token::token_program=token_program,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint=wallet_to_withdraw_from.owner == sender.key(),
+        constraint=wallet_to_withdraw_from.mint == token_mint.key()
+    )]
+    pub wallet_to_withdraw_from: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    #[account(
+        mut,
+        seeds = [constants::REGISTRY_SEED, token_mint.key().as_ref()],
+        bump,
+        realloc = registry.space_with_batch(round),
+        realloc::payer = sender,
+        realloc::zero = false,
+    )]
+    pub registry: Account<'info, Registry>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 32 + 1,
+        seeds = [constants::CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    // Only the upgrade authority of this program can create the config
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::TokenClaimProgram>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @ ErrorCode::InvalidSender)]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = config.guardian == guardian.key() @ ErrorCode::InvalidSender
+    )]
+    pub config: Account<'info, Config>,
+
+    pub guardian: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRegistry<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = Registry::space(Registry::DEFAULT_CLAIM_TYPES.len(), 0, 0),
+        seeds = [constants::REGISTRY_SEED, token_mint.key().as_ref()],
+        bump
+    )]
+    pub registry: Account<'info, Registry>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    // Only the upgrade authority of this program can create a registry
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::TokenClaimProgram>,
+
+    #[account(constraint = program_data.upgrade_authority_address == Some(authority.key()) @ ErrorCode::InvalidSender)]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(claim_type: u8)]
+pub struct UpdateRegistry<'info> {
+    #[account(
+        mut,
+        seeds = [constants::REGISTRY_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=registry.authority == authority.key() @ ErrorCode::InvalidSender,
+        realloc = registry.space_with_claim_type(claim_type),
+        realloc::payer = authority,
+        realloc::zero = false,
+    )]
+    pub registry: Account<'info, Registry>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Release<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.is_operator(&sender.key()) @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct UpdateUser<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.is_operator(&sender.key()) @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct UpdatePool<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.initializer == sender.key() @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct AcceptOwnership<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.pending_owner == sender.key() @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct RotateBeneficiary<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub sender: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Claim<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = sender,
+        associated_token::mint = token_mint,
+        associated_token::authority = sender,
+        associated_token::token_program = token_program,
+    )]
+    pub wallet_to_deposit_to: InterfaceAccount<'info, TokenAccount>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+
+    /// CHECK: address is checked, used to read the compliance attestation
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimMany<'info> {
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = sender,
+        associated_token::mint = token_mint,
+        associated_token::authority = sender,
+        associated_token::token_program = token_program,
+    )]
+    pub wallet_to_deposit_to: InterfaceAccount<'info, TokenAccount>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct ClaimTo<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint=destination.mint == token_mint.key() @ ErrorCode::InvalidDestination
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    /// CHECK: address is checked, used to read the compliance attestation
+    #[account(address = instructions_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Withdraw<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.is_treasurer(&sender.key()) @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = sender,
+        associated_token::mint = token_mint,
+        associated_token::authority = sender,
+        associated_token::token_program = token_program,
+    )]
+    pub wallet_to_deposit_to: InterfaceAccount<'info, TokenAccount>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Revoke<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.initializer == sender.key() @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    // initializer's own token account or a treasury, receives the unvested remainder
+    #[account(
+        mut,
+        constraint=destination.mint == token_mint.key() @ ErrorCode::InvalidDestination
+    )]
+    pub destination: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct Distribute<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.is_operator(&sender.key()) @ ErrorCode::InvalidSender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    pub sender: Signer<'info>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct FundEscrow<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = 8 + 32 + 32 + 8,
+        seeds = [constants::FUNDER_SEED, data_account.key().as_ref(), funder.key().as_ref()],
+        bump
+    )]
+    pub funder_account: Account<'info, FunderAccount>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint=funder_token_account.owner == funder.key(),
+        constraint=funder_token_account.mint == token_mint.key()
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [constants::REGISTRY_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub registry: Account<'info, Registry>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct RefundFunder<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [constants::FUNDER_SEED, data_account.key().as_ref(), funder.key().as_ref()],
+        bump,
+        close = funder
+    )]
+    pub funder_account: Account<'info, FunderAccount>,
+
+    /// CHECK: receives the rent of funder_account, tied to it through the seeds
+    #[account(mut)]
+    pub funder: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = funder,
+        associated_token::token_program = token_program,
+    )]
+    pub funder_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(round: u8 , claim_type: u8, batch: u8)]
+pub struct ClosePool<'info> {
+    #[account(
+        mut,
+        seeds = [&round.to_le_bytes().as_ref(), &claim_type.to_le_bytes().as_ref(), &batch.to_le_bytes().as_ref(), constants::DATA_SEED, token_mint.key().as_ref()],
+        bump,
+        constraint=data_account.load()?.initializer == sender.key() @ ErrorCode::InvalidSender,
+        close = sender
+    )]
+    pub data_account: AccountLoader<'info, DataAccount>,
+
+    #[account(
+        mut,
+       seeds=[constants::ESCROW_SEED, token_mint.key().as_ref(), data_account.key().as_ref()],
+        bump,
+    )]
+    pub escrow_wallet: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub sender: Signer<'info>,
+
+    #[account(mut)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = sender,
+        associated_token::mint = token_mint,
+        associated_token::authority = sender,
+        associated_token::token_program = token_program,
+    )]
+    pub wallet_to_deposit_to: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [constants::REGISTRY_SEED, token_mint.key().as_ref()],
+        bump,
+    )]
+    pub registry: Account<'info, Registry>,
+
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+
+    pub system_program: Program<'info, System>,
 }
 
+/// Allocation passed to initialize, every status of the beneficiary starts cleared
 #[derive(Default, Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct BeneficiaryInput {
+    pub key: Pubkey,           // 32
+    pub allocated_tokens: u64, // 8    // base units of token_mint
+    pub revocable: bool,       // 1    // allocation can be revoked by the initializer (Investors, SAFT)
+}
+
+// Flags are u8 (0 = false, 1 = true) so the record is plain old data and can be read in place
+#[zero_copy]
+#[derive(Default)]
 pub struct Beneficiary {
     pub key: Pubkey,           // 32
-    pub allocated_tokens: u64, // 8
-    pub is_claimed: bool,      //1
-    pub is_blocked: bool,      //1
-    pub in_process: bool,      //1    // to avoid race condition
+    pub pending_key: Pubkey,   // 32   // wallet an admin rotation is waiting to move to
+    pub allocated_tokens: u64, // 8    // base units of token_mint
+    pub claimed_tokens: u64,   // 8    // base units already claimed, allows partial claims
+    pub rotation_eta: i64,     // 8    // unix timestamp after which the pending rotation can execute
+    pub is_claimed: u8,        // 1
+    pub is_blocked: u8,        // 1
+    pub in_process: u8,        // 1    // to avoid race condition
+    pub is_withdrawn: u8,      // 1    // allocation moved back to the initializer
+    pub revocable: u8,         // 1    // allocation can be revoked by the initializer (Investors, SAFT)
+    pub is_revoked: u8,        // 1    // vesting frozen, allocated_tokens reduced to the vested amount
+    pub padding: [u8; 2],      // 2
+}
+
+impl Beneficiary {
+    pub fn unclaimed_tokens(&self) -> u64 {
+        self.allocated_tokens.saturating_sub(self.claimed_tokens)
+    }
+
+    /// Part of the allocation is still sitting in the escrow
+    pub fn is_unsettled(&self) -> bool {
+        self.is_claimed == 0 && self.is_withdrawn == 0 && self.in_process == 0
+    }
+
+    pub fn is_withdrawable(&self) -> bool {
+        self.is_blocked != 0 && self.is_unsettled()
+    }
+}
+
+// Zero copy, so a claim reads its record in place instead of deserializing every beneficiary.
+// Fields are ordered by alignment, the struct has no implicit padding
+#[account(zero_copy)]
+pub struct DataAccount {
+    pub token_amount: u64,     // 8     // base units of token_mint
+    pub total_withdrawn: u64,  // 8     // sum of allocations withdrawn, swept or revoked
+    pub total_claimed: u64,    // 8     // sum of tokens claimed by beneficiaries
+    pub rotation_delay: i64,   // 8     // seconds an admin rotation waits before it can execute
+    pub claim_deadline: i64,   // 8     // unix timestamp after which claims expire, 0 = none
+    pub vesting_start: i64,    // 8     // linear vesting start, no vesting when vesting_end is 0
+    pub vesting_end: i64,      // 8
+    pub total_allocated: u64,  // 8     // sum of all allocations at initialize
+    pub total_funded: u64,     // 8     // deposits made through fund_escrow
+    pub initializer: Pubkey,   // 32
+    pub escrow_wallet: Pubkey, // 32
+    pub token_mint: Pubkey,    // 32
+    pub compliance_signer: Pubkey, // 32   // claims need an Ed25519 attestation from this key, default = none
+    pub operator: Pubkey,      // 32    // day-to-day ops: block/unblock, release, rotations, distribution
+    pub treasurer: Pubkey,     // 32    // escrow withdrawals
+    pub pending_owner: Pubkey, // 32    // set by transfer_ownership, becomes initializer on accept
+    pub beneficiaries: [Beneficiary; constants::MAX_BENEFICIARIES], // MAX_BENEFICIARIES * 96, first beneficiary_count sorted by key
+    pub beneficiary_count: u32, // 4
+    pub open_funders: u32,     // 4     // funder accounts not refunded yet
+    pub released: u8,          // 1
+    pub round: u8,             // 1
+    pub claim_type: u8,        // 1     // label is stored in the Registry, defaults: 0-> IDO, 1-> SAFT, 2-> Tokensoft Presale, 3-> Utherverse Presale, 4-> Contest, 5-> Investors
+    pub batch: u8,             // 1
+    pub decimals: u8,          // 1
+    pub schedule_locked: u8,   // 1     // set by the first release, never cleared
+    pub padding: [u8; 2],      // 2
 }
 
 #[account]
 #[derive(Default)]
-pub struct DataAccount {
-    // Space in bytes: 8 + 1 + 1 + 1 + 8 + 32 + 32 + 32 + 1 + (4 + (100 * (32 + 8 + 8)))
-    pub released: bool,                  // 1
-    pub round: u8,                       //1
-    pub claim_type: u8, //1             //0-> IDO, 1-> SAFT, 2-> Tokensoft Presale, 3-> Utherverse Presale, 4-> Contest, 5-> Investors
-    pub batch: u8,      //1
+pub struct FunderAccount {
+    pub data_account: Pubkey, // 32
+    pub funder: Pubkey,       // 32
+    pub amount_funded: u64,   // 8
+}
+
+#[derive(Copy, Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub enum Role {
+    Operator,
+    Treasurer,
+}
+
+impl DataAccount {
+    /// Beneficiaries of the pool sorted by key, the slots after beneficiary_count are unused
+    pub fn beneficiaries(&self) -> &[Beneficiary] {
+        &self.beneficiaries[..self.beneficiary_count as usize]
+    }
+
+    pub fn beneficiaries_mut(&mut self) -> &mut [Beneficiary] {
+        &mut self.beneficiaries[..self.beneficiary_count as usize]
+    }
+
+    /// Opens or closes claims. The first release locks the vesting schedule for good, closing
+    /// claims again does not unlock it
+    pub fn set_released(&mut self, released: bool) {
+        self.released = released as u8;
+
+        if released {
+            self.schedule_locked = 1;
+        }
+    }
+
+    /// Changing the schedule once the pool was released, or after claims or revocations, would
+    /// change what users were told or what already vested
+    pub fn vesting_schedule_locked(&self) -> bool {
+        self.schedule_locked != 0
+            || self.total_claimed > 0
+            || self
+                .beneficiaries()
+                .iter()
+                .any(|beneficiary| beneficiary.is_revoked != 0)
+    }
+
+    pub fn claim_deadline(&self) -> Option<i64> {
+        (self.claim_deadline != 0).then_some(self.claim_deadline)
+    }
+
+    pub fn compliance_signer(&self) -> Option<Pubkey> {
+        (self.compliance_signer != Pubkey::default()).then_some(self.compliance_signer)
+    }
+
+    /// Part of `amount` paid for by the owner, in proportion to the owner's deposit
+    pub fn owner_share(&self, amount: u64) -> Result<u64> {
+        let owner_deposit = self
+            .token_amount
+            .checked_sub(self.total_funded)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        pro_rata(amount, owner_deposit, self.token_amount)
+    }
+
+    /// Refund of a co-funder: their share of every token that was not claimed by a beneficiary
+    pub fn funder_refund(&self, amount_funded: u64) -> Result<u64> {
+        let surplus = self
+            .token_amount
+            .checked_sub(self.total_claimed)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        pro_rata(surplus, amount_funded, self.token_amount)
+    }
+
+    /// Every allocation has left the escrow, either claimed or withdrawn
+    pub fn is_finished(&self) -> bool {
+        self.beneficiaries().iter().all(|beneficiary| {
+            beneficiary.in_process == 0
+                && (beneficiary.is_claimed != 0 || beneficiary.is_withdrawn != 0)
+        })
+    }
+
+    // The initializer is the owner of the pool and holds every role
+    pub fn is_operator(&self, key: &Pubkey) -> bool {
+        *key == self.initializer || (self.operator != Pubkey::default() && *key == self.operator)
+    }
+
+    pub fn is_treasurer(&self, key: &Pubkey) -> bool {
+        *key == self.initializer
+            || (self.treasurer != Pubkey::default() && *key == self.treasurer)
+    }
+
+    /// Beneficiaries are sorted by key, so lookups are a binary search
+    pub fn beneficiary_index(&self, wallet: &Pubkey) -> Result<usize> {
+        self.beneficiaries()
+            .binary_search_by_key(wallet, |beneficiary| beneficiary.key)
+            .map_err(|_| ErrorCode::BeneficiaryNotFound.into())
+    }
+
+    /// Part of the beneficiary's allocation that has vested at `now`
+    pub fn vested_tokens(&self, beneficiary: &Beneficiary, now: i64) -> Result<u64> {
+        // revoked allocations are already cut down to what had vested
+        if self.vesting_end == 0 || beneficiary.is_revoked != 0 || now >= self.vesting_end {
+            return Ok(beneficiary.allocated_tokens);
+        }
+        if now <= self.vesting_start {
+            return Ok(0);
+        }
+
+        let elapsed = (now - self.vesting_start) as u128;
+        let duration = (self.vesting_end - self.vesting_start) as u128;
+
+        let vested = (beneficiary.allocated_tokens as u128)
+            .checked_mul(elapsed)
+            .ok_or(ErrorCode::MathOverflow)?
+            / duration;
+
+        Ok(vested as u64)
+    }
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct ClaimTypeLabel {
+    pub claim_type: u8, // 1
+    pub label: String,  // 4 + MAX_LABEL_LEN
+}
+
+#[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct BatchEntry {
+    pub round: u8,              // 1
+    pub claim_type: u8,         // 1
+    pub batch: u8,              // 1
+    pub data_account: Pubkey,   // 32
+    pub token_amount: u64,      // 8
+    pub beneficiary_count: u32, // 4
+    pub created_at: i64,        // 8
+    pub is_closed: bool,        // 1
+}
+
+#[derive(Copy, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct RoundTotal {
+    pub round: u8,         // 1
     pub token_amount: u64, // 8
-    pub initializer: Pubkey, // 32
-    pub escrow_wallet: Pubkey, // 32
-    pub token_mint: Pubkey, // 32
-    pub beneficiaries: Vec<Beneficiary>, // (4 + (n * (32 + 8 + 8)))
-    pub decimals: u8,   // 1
+}
+
+#[account]
+pub struct Registry {
+    pub authority: Pubkey,                   // 32
+    pub token_mint: Pubkey,                  // 32
+    pub claim_types: Vec<ClaimTypeLabel>,    // (4 + (n * (1 + 4 + MAX_LABEL_LEN)))
+    pub batches: Vec<BatchEntry>,            // (4 + (n * 56))
+    pub round_totals: Vec<RoundTotal>,       // (4 + (n * 9))
+}
+
+impl Registry {
+    pub const MAX_LABEL_LEN: usize = 32;
+    pub const DEFAULT_CLAIM_TYPES: [&str; 6] = [
+        "IDO",
+        "SAFT",
+        "Tokensoft Presale",
+        "Utherverse Presale",
+        "Contest",
+        "Investors",
+    ];
+
+    pub fn space(claim_types: usize, batches: usize, round_totals: usize) -> usize {
+        8 + 32
+            + 32
+            + (4 + claim_types * (1 + 4 + Self::MAX_LABEL_LEN))
+            + (4 + batches * (1 + 1 + 1 + 32 + 8 + 4 + 8 + 1))
+            + (4 + round_totals * (1 + 8))
+    }
+
+    /// Size once initialize registered a batch of `round`, only a new round adds a round total
+    pub fn space_with_batch(&self, round: u8) -> usize {
+        let new_round = !self.round_totals.iter().any(|round_total| round_total.round == round);
+
+        Self::space(
+            self.claim_types.len(),
+            self.batches.len() + 1,
+            self.round_totals.len() + new_round as usize,
+        )
+    }
+
+    /// Size once set_claim_type_label ran, renaming an existing claim type needs no extra room
+    pub fn space_with_claim_type(&self, claim_type: u8) -> usize {
+        let new_claim_type = !self
+            .claim_types
+            .iter()
+            .any(|claim_type_label| claim_type_label.claim_type == claim_type);
+
+        Self::space(
+            self.claim_types.len() + new_claim_type as usize,
+            self.batches.len(),
+            self.round_totals.len(),
+        )
+    }
+
+    pub fn add_to_round_total(&mut self, round: u8, amount: u64) -> Result<()> {
+        match self
+            .round_totals
+            .iter_mut()
+            .find(|round_total| round_total.round == round)
+        {
+            Some(round_total) => {
+                round_total.token_amount = round_total
+                    .token_amount
+                    .checked_add(amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+            None => self.round_totals.push(RoundTotal {
+                round,
+                token_amount: amount,
+            }),
+        }
+
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct Config {
+    pub guardian: Pubkey, // 32
+    pub paused: bool,     // 1
+}
+
+#[event]
+pub struct PauseStatusChanged {
+    pub guardian: Pubkey,
+    pub paused: bool,
+    pub timestamp: i64,
 }
 
 #[error_code]
@@ -406,4 +2359,303 @@ pub enum ErrorCode {
     BeneficiaryNotFound,
     #[msg("Batch already exist")]
     IsBatched,
+    #[msg("Allocation has already been withdrawn from escrow")]
+    AllocationWithdrawn,
+    #[msg("No blocked allocations left to withdraw")]
+    NothingToWithdraw,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Total allocated tokens exceed the deposited amount, escrow needs more funding")]
+    AllocationExceedsAmount,
+    #[msg("Amount must be greater than zero")]
+    InvalidAmount,
+    #[msg("Amount exceeds the unclaimed allocation")]
+    AmountExceedsAllocation,
+    #[msg("Destination token account does not match the claim mint")]
+    InvalidDestination,
+    #[msg("Rotation delay can not be negative")]
+    InvalidRotationDelay,
+    #[msg("Allocation can not be rotated")]
+    RotationNotAllowed,
+    #[msg("New wallet is not a valid beneficiary key")]
+    InvalidNewWallet,
+    #[msg("Wallet already exists in this batch")]
+    BeneficiaryExists,
+    #[msg("A wallet rotation is pending for this beneficiary")]
+    RotationPending,
+    #[msg("No wallet rotation is pending for this beneficiary")]
+    NoPendingRotation,
+    #[msg("Rotation timelock has not passed yet")]
+    RotationLocked,
+    #[msg("Pool still has allocations that are not claimed or withdrawn")]
+    PoolNotFinished,
+    #[msg("Claim deadline must be in the future")]
+    InvalidClaimDeadline,
+    #[msg("Claim deadline has passed")]
+    ClaimDeadlinePassed,
+    #[msg("Claim deadline has not passed yet")]
+    ClaimDeadlineNotReached,
+    #[msg("No vested tokens left to claim")]
+    NothingVested,
+    #[msg("Vesting end must be after vesting start")]
+    InvalidVestingSchedule,
+    #[msg("Vesting schedule can not change after release, claims or revocations")]
+    VestingScheduleLocked,
+    #[msg("Allocation is not revocable")]
+    NotRevocable,
+    #[msg("Allocation has already been revoked")]
+    AlreadyRevoked,
+    #[msg("Allocation can not be revoked")]
+    RevokeNotAllowed,
+    #[msg("Claim requires a compliance attestation")]
+    MissingAttestation,
+    #[msg("Compliance attestation is invalid")]
+    InvalidAttestation,
+    #[msg("Compliance attestation has expired")]
+    AttestationExpired,
+    #[msg("Remaining accounts must be data account and escrow pairs of this mint")]
+    InvalidRemainingAccounts,
+    #[msg("No eligible allocations to claim")]
+    NothingToClaim,
+    #[msg("Beneficiaries must be sorted by key without duplicates")]
+    BeneficiariesNotSorted,
+    #[msg("Claim type is not registered")]
+    InvalidClaimType,
+    #[msg("Label must be between 1 and 32 bytes")]
+    InvalidLabel,
+    #[msg("Co-funders must be refunded before the pool can be closed")]
+    FundersNotRefunded,
+    #[msg("Program is paused")]
+    ProgramPaused,
+    #[msg("Mint has a Token-2022 extension that is not supported")]
+    UnsupportedMintExtension,
+    #[msg("A batch holds at most MAX_BENEFICIARIES beneficiaries")]
+    TooManyBeneficiaries,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::Zeroable;
+
+    /// Empty pool and the attestation signer, pool and wallet keys the tests start from
+    struct Fixture {
+        data_account: DataAccount,
+        signer: Pubkey,
+        pool: Pubkey,
+        wallet: Pubkey,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                data_account: DataAccount::zeroed(),
+                signer: Pubkey::new_unique(),
+                pool: Pubkey::new_unique(),
+                wallet: Pubkey::new_unique(),
+            }
+        }
+
+        /// Pool funded with 600 by the owner and 300 + 100 by two co-funders
+        fn co_funded(mut self) -> Self {
+            self.data_account.token_amount = 1_000;
+            self.data_account.total_funded = 400;
+            self.data_account.total_allocated = 900;
+            self
+        }
+
+        fn claimed(mut self, total_claimed: u64) -> Self {
+            self.data_account.total_claimed = total_claimed;
+            self
+        }
+
+        /// Attestation of the fixture's wallet for the fixture's pool
+        fn attestation(&self, expiry: i64) -> Vec<u8> {
+            attestation(&self.signer, &self.pool, &self.wallet, expiry)
+        }
+
+        fn verify(&self, data: &[u8], now: i64) -> Result<()> {
+            verify_attestation_data(data, &self.signer, &self.pool, &self.wallet, now)
+        }
+    }
+
+    #[test]
+    fn registry_grows_by_what_is_added() {
+        let mut registry = Registry {
+            authority: Pubkey::new_unique(),
+            token_mint: Pubkey::new_unique(),
+            claim_types: vec![ClaimTypeLabel { claim_type: 0, label: "IDO".to_string() }],
+            batches: vec![],
+            round_totals: vec![RoundTotal { round: 1, token_amount: 1_000 }],
+        };
+
+        // another batch of round 1 only needs a batch entry, round 2 also a round total
+        assert_eq!(registry.space_with_batch(1), Registry::space(1, 1, 1));
+        assert_eq!(registry.space_with_batch(2), Registry::space(1, 1, 2));
+
+        // renaming a claim type keeps the size
+        assert_eq!(registry.space_with_claim_type(0), Registry::space(1, 0, 1));
+        assert_eq!(registry.space_with_claim_type(6), Registry::space(2, 0, 1));
+
+        registry.round_totals.clear();
+        assert_eq!(registry.space_with_batch(1), Registry::space(1, 1, 1));
+    }
+
+    #[test]
+    fn first_release_locks_the_vesting_schedule() {
+        let mut data_account = Fixture::new().data_account;
+        assert!(!data_account.vesting_schedule_locked());
+
+        data_account.set_released(true);
+        data_account.set_released(false);
+
+        assert_eq!(data_account.released, 0);
+        assert!(data_account.vesting_schedule_locked());
+    }
+
+    #[test]
+    fn batch_claims_only_skip_ineligible_wallets() {
+        assert!(is_ineligible_claim(&ErrorCode::NothingVested.into()));
+        assert!(is_ineligible_claim(&ErrorCode::BeneficiaryNotFound.into()));
+        assert!(is_ineligible_claim(&ErrorCode::AttestationExpired.into()));
+
+        assert!(!is_ineligible_claim(&ErrorCode::MathOverflow.into()));
+        assert!(!is_ineligible_claim(&ErrorCode::InvalidRemainingAccounts.into()));
+        assert!(!is_ineligible_claim(&ErrorCode::InvalidDestination.into()));
+        assert!(!is_ineligible_claim(
+            &anchor_lang::error::ErrorCode::AccountDidNotDeserialize.into()
+        ));
+    }
+
+    #[test]
+    fn withdrawals_pay_the_owner_share() {
+        let data_account = Fixture::new().co_funded().data_account;
+
+        assert_eq!(data_account.owner_share(200).unwrap(), 120);
+        assert_eq!(data_account.owner_share(0).unwrap(), 0);
+    }
+
+    #[test]
+    fn refund_funder_splits_the_surplus_pro_rata() {
+        let data_account = Fixture::new().co_funded().claimed(700).data_account;
+
+        // 200 of blocked allocations were withdrawn, the owner received their share only
+        let owner_withdrawn = data_account.owner_share(200).unwrap();
+        let mut escrow = data_account.token_amount - data_account.total_claimed - owner_withdrawn;
+
+        let refund_small = data_account.funder_refund(100).unwrap();
+        let refund_large = data_account.funder_refund(300).unwrap();
+
+        assert_eq!(refund_large, 90);
+        assert_eq!(refund_small, 30);
+
+        // The escrow covers both refunds, the rest goes to the owner in close_pool
+        escrow -= refund_small + refund_large;
+        assert_eq!(owner_withdrawn + escrow, 180);
+    }
+
+    #[test]
+    fn refund_funder_without_co_funding() {
+        let mut data_account = Fixture::new().co_funded().data_account;
+        data_account.total_funded = 0;
+
+        assert_eq!(data_account.owner_share(200).unwrap(), 200);
+
+        data_account.token_amount = 0;
+        assert_eq!(data_account.funder_refund(100).unwrap(), 0);
+    }
+
+    /// Ed25519 instruction data with one signature over `pool || wallet || expiry`
+    fn attestation(signer: &Pubkey, pool: &Pubkey, wallet: &Pubkey, expiry: i64) -> Vec<u8> {
+        let public_key_offset: u16 = 16;
+        let signature_offset: u16 = public_key_offset + 32;
+        let message_offset: u16 = signature_offset + 64;
+
+        let mut data = vec![1u8, 0];
+        for value in [
+            signature_offset,
+            u16::MAX,
+            public_key_offset,
+            u16::MAX,
+            message_offset,
+            32 + 32 + 8,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0u8; 64]);
+        data.extend_from_slice(pool.as_ref());
+        data.extend_from_slice(wallet.as_ref());
+        data.extend_from_slice(&expiry.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn attestation_accepted() {
+        let fixture = Fixture::new();
+        let data = fixture.attestation(1_000);
+
+        assert!(fixture.verify(&data, 1_000).is_ok());
+    }
+
+    #[test]
+    fn attestation_expired() {
+        let fixture = Fixture::new();
+        let data = fixture.attestation(1_000);
+
+        assert_eq!(
+            fixture.verify(&data, 1_001).unwrap_err(),
+            ErrorCode::AttestationExpired.into()
+        );
+    }
+
+    #[test]
+    fn attestation_for_another_signer_pool_or_wallet() {
+        let fixture = Fixture::new();
+        let (signer, pool, wallet) = (fixture.signer, fixture.pool, fixture.wallet);
+        let other = Pubkey::new_unique();
+
+        for data in [
+            attestation(&other, &pool, &wallet, 1_000),
+            attestation(&signer, &other, &wallet, 1_000),
+            attestation(&signer, &pool, &other, 1_000),
+        ] {
+            assert_eq!(
+                fixture.verify(&data, 0).unwrap_err(),
+                ErrorCode::InvalidAttestation.into()
+            );
+        }
+    }
+
+    #[test]
+    fn attestation_layout_is_checked() {
+        let fixture = Fixture::new();
+
+        // the message lives in another instruction of the transaction
+        let mut data = fixture.attestation(1_000);
+        data[14..16].copy_from_slice(&0u16.to_le_bytes());
+
+        assert_eq!(
+            fixture.verify(&data, 0).unwrap_err(),
+            ErrorCode::InvalidAttestation.into()
+        );
+
+        // more than one signature
+        let mut data = fixture.attestation(1_000);
+        data[0] = 2;
+
+        assert_eq!(
+            fixture.verify(&data, 0).unwrap_err(),
+            ErrorCode::InvalidAttestation.into()
+        );
+
+        // truncated message
+        let data = fixture.attestation(1_000);
+
+        assert_eq!(
+            fixture.verify(&data[..100], 0).unwrap_err(),
+            ErrorCode::InvalidAttestation.into()
+        );
+    }
 }
@@ -1,10 +1,19 @@
 use anchor_lang::prelude::*;
 use anchor_spl::{
-    associated_token::AssociatedToken,
-    token::{transfer, Mint, Token, TokenAccount, Transfer},
+    associated_token::{get_associated_token_address_with_program_id, AssociatedToken},
+    metadata::{Metadata, MetadataAccount},
+    token_2022::spl_token_2022::{
+        self,
+        extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+    },
+    token_interface::{
+        burn, mint_to, transfer_checked, Burn, Mint, MintTo, TokenAccount, TokenInterface,
+        TransferChecked,
+    },
 };
 
 use solana_program::clock::Clock;
+use solana_program::keccak;
 use solana_program::pubkey;
 
 declare_id!("EHewQr3kinhMsdRQgW5pPLRKo14iwrhscygCDrKKPuEy");
@@ -13,6 +22,18 @@ pub mod constants {
     pub const VAULT_SEED: &[u8] = b"vault";
     pub const STAKE_INFO_SEED: &[u8] = b"stake_info";
     pub const TOKEN_SEED: &[u8] = b"token";
+    pub const CONFIG_SEED: &[u8] = b"config";
+    pub const WALLET_STAKE_SEED: &[u8] = b"wallet_stake";
+    pub const RECEIPT_MINT_SEED: &[u8] = b"receipt_mint";
+    pub const STREAM_VAULT_SEED: &[u8] = b"stream_vault";
+    pub const BOOST_LOCK_SEED: &[u8] = b"boost_lock";
+    pub const MAX_REWARD_STREAMS: usize = 4;
+    pub const LAYOUT_VERSION: u8 = 1; // PoolInfo and StakeInfo layout written by this program
+    pub const REWARD_MODE_APY: u8 = 0;
+    pub const REWARD_MODE_EMISSIONS: u8 = 1;
+    pub const ACC_PRECISION: u128 = 1_000_000_000_000;
+    pub const BPS_DENOMINATOR: u64 = 10_000;
+    pub const MAX_BOOST_BPS: u64 = 10_000;
     pub const SLOTS_PER_DAY: u64 = 216000;
     pub const SLOTS_PER_WEEK: u64 = SLOTS_PER_DAY * 7;
     pub const SLOTS_PER_MONTH: u64 = SLOTS_PER_DAY * 30;
@@ -31,19 +52,22 @@ pub mod staking_program {
     ) -> Result<()> {
         // Validate input parameters
 
-        if lock_time <= 0 {
+        if lock_time == 0 {
             return Err(ErrorCode::InvalidLockTime.into());
         }
-        if apy <= 0 {
+        if apy == 0 {
             return Err(ErrorCode::InvalidApy.into());
         }
-        if apy_denominator <= 0 {
+        if apy_denominator == 0 {
             return Err(ErrorCode::InvalidApyDenominator.into());
         }
         if roi_type > 2 {
             return Err(ErrorCode::InvalidRoiType.into());
         }
 
+        assert_supported_mint(&ctx.accounts.mint.to_account_info())?;
+        assert_supported_mint(&ctx.accounts.reward_mint.to_account_info())?;
+
         let pool_info = &mut ctx.accounts.pool_info;
 
         // Ensure the admin is set correctly
@@ -59,6 +83,101 @@ pub mod staking_program {
         pool_info.apy_denominator = apy_denominator;
         pool_info.roi_type = roi_type; // 0-> Daily, 1-> Weekly, 2-> Monthly
         pool_info.token = ctx.accounts.mint.key();
+        pool_info.reward_mint = ctx.accounts.reward_mint.key();
+        pool_info.token_decimals = ctx.accounts.mint.decimals;
+        pool_info.reward_decimals = ctx.accounts.reward_mint.decimals;
+        pool_info.reward_mode = constants::REWARD_MODE_APY;
+        pool_info.version = constants::LAYOUT_VERSION;
+
+        Ok(())
+    }
+
+    pub fn initialize_emission_pool(
+        ctx: Context<Initialize>,
+        lock_time: u64,
+        reward_rate: u64,
+        emission_end: i64,
+    ) -> Result<()> {
+        let clock = Clock::get()?;
+
+        // Validate input parameters
+
+        if lock_time == 0 {
+            return Err(ErrorCode::InvalidLockTime.into());
+        }
+        if reward_rate == 0 {
+            return Err(ErrorCode::InvalidRewardRate.into());
+        }
+        if emission_end <= clock.unix_timestamp {
+            return Err(ErrorCode::InvalidEmissionEnd.into());
+        }
+
+        assert_supported_mint(&ctx.accounts.mint.to_account_info())?;
+        assert_supported_mint(&ctx.accounts.reward_mint.to_account_info())?;
+
+        let pool_info = &mut ctx.accounts.pool_info;
+
+        // Ensure the admin is set correctly
+        if pool_info.admin != Pubkey::default() && pool_info.admin != ctx.accounts.admin.key() {
+            return Err(ErrorCode::UnauthorizedAdmin.into());
+        }
+
+        //Setup pool info states
+        pool_info.admin = ctx.accounts.admin.key();
+        pool_info.token_vault = ctx.accounts.token_vault_account.key();
+        pool_info.lock_time = lock_time;
+        pool_info.roi_type = 0; // only used for the claim cycle bookkeeping of stake
+        pool_info.token = ctx.accounts.mint.key();
+        pool_info.reward_mint = ctx.accounts.reward_mint.key();
+        pool_info.token_decimals = ctx.accounts.mint.decimals;
+        pool_info.reward_decimals = ctx.accounts.reward_mint.decimals;
+        pool_info.reward_mode = constants::REWARD_MODE_EMISSIONS;
+        pool_info.version = constants::LAYOUT_VERSION;
+        pool_info.reward_rate = reward_rate; // reward_mint base units per second for the whole pool
+        pool_info.emission_end = emission_end;
+        pool_info.last_update_time = clock.unix_timestamp;
+        pool_info.acc_reward_per_share = 0;
+
+        Ok(())
+    }
+
+    pub fn initialize_config(ctx: Context<InitializeConfig>, guardian: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+
+        config.guardian = guardian;
+        config.paused = false;
+
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        // Only the guardian can pause or unpause the program
+        if ctx.accounts.guardian.key() != ctx.accounts.config.guardian {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.paused = paused;
+
+        let clock = Clock::get()?;
+
+        emit!(PauseStatusChanged {
+            guardian: ctx.accounts.guardian.key(),
+            paused,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn set_guardian(ctx: Context<UpdateConfig>, new_guardian: Pubkey) -> Result<()> {
+        // Only the current guardian can hand over the role
+        if ctx.accounts.guardian.key() != ctx.accounts.config.guardian {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let config = &mut ctx.accounts.config;
+        config.guardian = new_guardian;
 
         Ok(())
     }
@@ -68,6 +187,8 @@ pub mod staking_program {
         stake_counter: u64,
         amount: u64,
         autostake: bool,
+        proof: Vec<[u8; 32]>,
+        max_allowed: u64,
     ) -> Result<()> {
         let stake_info = &mut ctx.accounts.stake_info_account;
 
@@ -82,7 +203,7 @@ pub mod staking_program {
         }
 
         //Ensure that the amount is a positive integer
-        if amount <= 0 {
+        if amount == 0 {
             return Err(ErrorCode::NoTokens.into());
         }
 
@@ -93,6 +214,28 @@ pub mod staking_program {
 
         let clock = Clock::get()?;
 
+        //Ensure that the pool accepts deposits right now
+        let pool_limits = &ctx.accounts.pool_info;
+        if pool_limits.deposit_start != 0 && clock.unix_timestamp < pool_limits.deposit_start {
+            return Err(ErrorCode::DepositsNotOpen.into());
+        }
+        if pool_limits.deposit_end != 0 && clock.unix_timestamp > pool_limits.deposit_end {
+            return Err(ErrorCode::DepositsClosed.into());
+        }
+
+        //Ensure that the signer is on the allowlist of gated pools
+        if pool_limits.merkle_root != [0u8; 32] {
+            let leaf = keccak::hashv(&[
+                ctx.accounts.signer.key().as_ref(),
+                &max_allowed.to_le_bytes(),
+            ])
+            .0;
+
+            if !verify_merkle_proof(&proof, pool_limits.merkle_root, leaf) {
+                return Err(ErrorCode::NotAllowlisted.into());
+            }
+        }
+
         //Setup states before transfer
         stake_info.deposit_timestamp = clock.unix_timestamp;
         msg!("Deposit Timestamp: {}", stake_info.deposit_timestamp);
@@ -101,29 +244,125 @@ pub mod staking_program {
         stake_info.in_process = true;
         stake_info.stake_seed = stake_counter;
         stake_info.autostake = autostake;
-        let pool_info = &ctx.accounts.pool_info;
+        stake_info.owner = ctx.accounts.signer.key();
+        stake_info.staker = ctx.accounts.signer.key();
+        stake_info.version = constants::LAYOUT_VERSION;
+        let pool_info = &mut ctx.accounts.pool_info;
         let lock_time = pool_info.lock_time;
         let roi_type = pool_info.roi_type;
 
+        //Emission pools pay pro rata rewards only, there is nothing to autostake
+        if autostake && pool_info.reward_mode == constants::REWARD_MODE_EMISSIONS {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
+
         let stake_amount = (amount)
             .checked_mul(10u64.pow(ctx.accounts.mint.decimals as u32))
             .unwrap();
 
+        let balance_before = ctx.accounts.stake_account.amount;
+
         //transfer the tokens from user account to user stake account
-        transfer(
+        transfer_checked(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
-                Transfer {
+                TransferChecked {
                     from: ctx.accounts.user_token_account.to_account_info(),
+                    mint: ctx.accounts.mint.to_account_info(),
                     to: ctx.accounts.stake_account.to_account_info(),
                     authority: ctx.accounts.signer.to_account_info(),
                 },
             ),
             stake_amount,
+            ctx.accounts.mint.decimals,
         )?;
 
+        // Transfer fee mints deliver less than was sent, only what arrived is staked
+        ctx.accounts.stake_account.reload()?;
+        let received_amount = ctx
+            .accounts
+            .stake_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::InvalidAmount)?;
+
+        //Ensure that the position and the wallet stay inside the pool limits (0 = no limit)
+        if pool_info.min_stake != 0 && received_amount < pool_info.min_stake {
+            return Err(ErrorCode::StakeBelowMinimum.into());
+        }
+        if pool_info.max_stake != 0 && received_amount > pool_info.max_stake {
+            return Err(ErrorCode::StakeAboveMaximum.into());
+        }
+
+        let wallet_stake = &mut ctx.accounts.wallet_stake;
+
+        // First stake of this wallet in the pool, the only moment a referrer can be bound
+        if wallet_stake.wallet == Pubkey::default() {
+            wallet_stake.wallet = ctx.accounts.signer.key();
+            wallet_stake.pool = pool_info.key();
+
+            if let Some(referrer_wallet_stake) = ctx.accounts.referrer_wallet_stake.as_mut() {
+                // The referrer must have staked in this pool before, which also rules out cycles
+                if referrer_wallet_stake.wallet == Pubkey::default()
+                    || referrer_wallet_stake.wallet == wallet_stake.wallet
+                    || referrer_wallet_stake.pool != wallet_stake.pool
+                {
+                    return Err(ErrorCode::InvalidReferrer.into());
+                }
+
+                wallet_stake.referrer = referrer_wallet_stake.wallet;
+                referrer_wallet_stake.referral_count += 1;
+            }
+        }
+        stake_info.referrer = wallet_stake.referrer;
+
+        wallet_stake.total_staked = wallet_stake
+            .total_staked
+            .checked_add(received_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if pool_info.max_per_wallet != 0 && wallet_stake.total_staked > pool_info.max_per_wallet {
+            return Err(ErrorCode::WalletCapExceeded.into());
+        }
+        // Allowlist leaves can carry their own cap per wallet (0 = no cap)
+        if pool_info.merkle_root != [0u8; 32]
+            && max_allowed != 0
+            && wallet_stake.total_staked > max_allowed
+        {
+            return Err(ErrorCode::AllowanceExceeded.into());
+        }
+
+        let new_total_staked = pool_info
+            .total_staked
+            .checked_add(received_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if pool_info.max_total_stake != 0 && new_total_staked > pool_info.max_total_stake {
+            return Err(ErrorCode::PoolCapacityReached.into());
+        }
+
+        // Settle the accumulator before total_staked changes, the new stake only earns from now on
+        pool_info.update_emissions(clock.unix_timestamp)?;
+        stake_info.reward_debt = pool_info.accrued_rewards(received_amount)?;
+        pool_info.total_staked = new_total_staked;
+
+        // Receipt pools mint the liquid staking token of the pool 1:1 to the principal that arrived
+        stake_info.receipt_amount = 0;
+        if pool_info.receipts_enabled {
+            mint_receipt(
+                pool_info,
+                &ctx.accounts.receipt_mint,
+                &ctx.accounts.user_receipt_token_account,
+                &ctx.accounts.receipt_token_program,
+                &ctx.accounts.signer.key(),
+                received_amount,
+            )?;
+            stake_info.receipt_amount = received_amount;
+        } else if ctx.accounts.receipt_mint.is_some() {
+            return Err(ErrorCode::InvalidReceiptAccounts.into());
+        }
+
         //update remaining states of stake info account
-        stake_info.staked_amount = stake_amount;
+        stake_info.staked_amount = received_amount;
+        stake_info.deposited_amount = received_amount;
         stake_info.end_time = stake_info.stake_at_slot + lock_time;
         stake_info.unclaimed_rewards = 0;
         stake_info.last_interaction_time = clock.slot;
@@ -150,7 +389,10 @@ pub mod staking_program {
         Ok(())
     }
 
-    pub fn destake(ctx: Context<DeStake>, stake_counter: u64) -> Result<()> {
+    pub fn destake<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DeStake<'info>>,
+        _stake_counter: u64,
+    ) -> Result<()> {
         let stake_info = &mut ctx.accounts.stake_info_account;
         let pool_info = &mut ctx.accounts.pool_info;
 
@@ -173,53 +415,73 @@ pub mod staking_program {
 
         stake_info.in_process = true;
 
-        let stake_amount = ctx.accounts.stake_account.amount;
-
-        if stake_info.autostake {
-            // Determine cycle type (daily/weekly/monthly/etc)
-            let cycle_duration = match pool_info.roi_type {
-                0 => constants::SLOTS_PER_DAY,   // Daily reward calculation
-                1 => constants::SLOTS_PER_WEEK,  // Weekly reward calculation
-                2 => constants::SLOTS_PER_MONTH, // Monthly reward calculation
-                _ => return Err(ErrorCode::InvalidRoiType.into()),
+        //Receipt positions can only be closed by burning their receipt tokens
+        if stake_info.receipt_amount > 0 {
+            let (receipt_mint, user_receipt_token_account, receipt_token_program) = match (
+                &ctx.accounts.receipt_mint,
+                &ctx.accounts.user_receipt_token_account,
+                &ctx.accounts.receipt_token_program,
+            ) {
+                (Some(receipt_mint), Some(user_receipt_token_account), Some(receipt_token_program)) => {
+                    (receipt_mint, user_receipt_token_account, receipt_token_program)
+                }
+                _ => return Err(ErrorCode::InvalidReceiptAccounts.into()),
             };
 
-            let total_cycles = stake_info.total_claim_cycles;
-            let current_stake = stake_info.staked_amount;
-            // Safely convert total_cycles to i32
-            let total_cycles_i32 = total_cycles
-                .try_into()
-                .map_err(|_| ErrorCode::InvalidCycleCount)?;
-
-            // Calculate the effective APY for the cycle
-            let apy_per_cycle = pool_info.apy as f64 / pool_info.apy_denominator as f64
-                * (cycle_duration as f64 / constants::SLOTS_PER_YEAR as f64);
+            if user_receipt_token_account.mint != receipt_mint.key() {
+                return Err(ErrorCode::InvalidReceiptAccounts.into());
+            }
 
-            // Calculate total compounded reward
-            let total_reward = current_stake as f64 * (1.0 + apy_per_cycle).powi(total_cycles_i32)
-                - current_stake as f64;
+            let burn_receipt_accounts = Burn {
+                mint: receipt_mint.to_account_info(),
+                from: user_receipt_token_account.to_account_info(),
+                authority: ctx.accounts.signer.to_account_info(),
+            };
 
-            let bump_for_vault = ctx.bumps.token_vault_account;
+            let ctxx = CpiContext::new(receipt_token_program.to_account_info(), burn_receipt_accounts);
 
-            let signer_seeds_for_reward: &[&[&[u8]]] =
-                &[&[constants::VAULT_SEED, &[bump_for_vault]]];
+            burn(ctxx, stake_info.receipt_amount)?;
+        }
 
-            //Transfer the rewards from vault to user wallet
+        // The stake account holds every position of the owner in this pool, only this one is paid out
+        let stake_amount = stake_info.staked_amount;
+        let decimals = ctx.accounts.mint.decimals;
 
-            let transfer_from_vault_accounts = Transfer {
-                from: ctx.accounts.token_vault_account.to_account_info(),
-                to: ctx.accounts.user_token_account.to_account_info(),
-                authority: ctx.accounts.token_vault_account.to_account_info(),
-            };
+        verify_boost(
+            stake_info,
+            pool_info,
+            ctx.accounts.boost_lock.as_ref(),
+            ctx.accounts.boost_token_account.as_ref(),
+        )?;
 
-            let ctxx = CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                transfer_from_vault_accounts,
-                signer_seeds_for_reward,
-            );
+        settle_position_rewards(
+            pool_info,
+            stake_info,
+            &ctx.accounts.token_vault_account,
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.user_reward_token_account,
+            &ctx.accounts.reward_token_program,
+            ctx.accounts.referrer_wallet_stake.as_deref_mut(),
+        )?;
 
-            transfer(ctxx, total_reward as u64)?;
-        }
+        pool_info.total_staked = pool_info.total_staked.saturating_sub(stake_info.staked_amount);
+
+        // The per wallet cap counts deposits, restaked rewards are not part of it
+        let wallet_stake = &mut ctx.accounts.wallet_stake;
+        wallet_stake.total_staked = wallet_stake
+            .total_staked
+            .saturating_sub(stake_info.deposited_amount);
+
+        // Without stream accounts the outstanding bonus rewards are forfeited,
+        // destake itself must never be blocked by a reward stream
+        pay_reward_streams(
+            pool_info,
+            stake_info,
+            ctx.remaining_accounts,
+            &ctx.accounts.signer.key(),
+            clock.slot,
+            ctx.program_id,
+        )?;
 
         let staker = ctx.accounts.signer.key();
         let poolkey = ctx.accounts.pool_info.key();
@@ -235,8 +497,9 @@ pub mod staking_program {
 
         //transfer the amount from stake account to user walllet
 
-        let transfer_from_stake_accounts = Transfer {
+        let transfer_from_stake_accounts = TransferChecked {
             from: ctx.accounts.stake_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
             to: ctx.accounts.user_token_account.to_account_info(),
             authority: ctx.accounts.stake_account.to_account_info(),
         };
@@ -247,7 +510,7 @@ pub mod staking_program {
             signer_seeds_for_user_stake,
         );
 
-        transfer(ctx, stake_amount)?;
+        transfer_checked(ctx, stake_amount, decimals)?;
 
         //update the states
 
@@ -258,26 +521,44 @@ pub mod staking_program {
         stake_info.total_claimed = 0;
         stake_info.last_interaction_time = clock.slot;
         stake_info.next_claim_time = 0;
+        stake_info.reward_debt = 0;
+        stake_info.deposited_amount = 0;
+        stake_info.receipt_amount = 0;
+        stake_info.stream_last_slot = [0; constants::MAX_REWARD_STREAMS];
+        stake_info.stream_claimed = [0; constants::MAX_REWARD_STREAMS];
 
         stake_info.in_process = false;
 
         Ok(())
     }
 
-    pub fn calculate_rewards(ctx: Context<Reward>, stake_counter: u64) -> Result<u64> {
+    pub fn calculate_rewards(ctx: Context<Reward>, _stake_counter: u64) -> Result<u64> {
         let stake_info = &mut ctx.accounts.stake_info_account;
         let pool_info = &mut ctx.accounts.pool_info;
 
         //Input parms Validations
-        if stake_info.staked_amount <= 0 {
+        if stake_info.staked_amount == 0 {
             return Err(ErrorCode::InvalidAmount.into());
         }
 
-        if pool_info.apy_denominator <= 0 {
+        // Emission pools report what has accrued so far
+        if pool_info.reward_mode == constants::REWARD_MODE_EMISSIONS {
+            let clock = Clock::get()?;
+            pool_info.update_emissions(clock.unix_timestamp)?;
+
+            let pending_rewards = pool_info.pending_rewards(stake_info)?;
+            msg!("Total amount: {}", pending_rewards);
+
+            return Ok(pending_rewards);
+        }
+
+        if pool_info.apy_denominator == 0 {
             return Err(ErrorCode::InvalidApyDenominator.into());
         }
 
-        let reward_rate = stake_info.staked_amount * pool_info.apy
+        // Rewards are paid in reward_mint, so the stake is expressed in its base units first
+        let reward_rate = pool_info.to_reward_units(stake_info.staked_amount)?
+            * pool_info.apy
             / pool_info.apy_denominator
             / constants::SLOTS_PER_YEAR;
         let total_reward = match ctx.accounts.pool_info.roi_type {
@@ -286,18 +567,36 @@ pub mod staking_program {
             2 => reward_rate * constants::SLOTS_PER_MONTH, // Monthly reward calculation
             _ => return Err(ErrorCode::InvalidRoiType.into()), // Default case if roi_type is unhandled
         };
+        // A boosted position earns boost_bps on top
+        let total_reward = total_reward + stake_info.boost_reward(total_reward, 1)?;
         msg!("Total amount: {}", total_reward);
 
         Ok(total_reward)
     }
 
-    pub fn claim_rewards(ctx: Context<Reward>, stake_counter: u64) -> Result<()> {
+    pub fn claim_rewards<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Reward<'info>>,
+        _stake_counter: u64,
+    ) -> Result<()> {
         let stake_info = &mut ctx.accounts.stake_info_account;
         let pool_info = &mut ctx.accounts.pool_info;
         let roi_type = pool_info.roi_type;
         let clock = Clock::get()?;
         let bump_for_vault = ctx.bumps.token_vault_account;
-        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[constants::VAULT_SEED, &[bump_for_vault]]];
+        let pool_key = pool_info.key();
+        let reward_mint_key = pool_info.reward_mint;
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::VAULT_SEED,
+            pool_key.as_ref(),
+            reward_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+        let decimals = ctx.accounts.reward_mint.decimals;
+
+        //Emission pools are claimed with claim_emissions
+        if pool_info.reward_mode != constants::REWARD_MODE_APY {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
 
         //Ensure that the user has staked some tokens before claim
         if !stake_info.is_staked {
@@ -326,7 +625,16 @@ pub mod staking_program {
 
         stake_info.in_process = true;
 
-        let reward_rate = stake_info.staked_amount * pool_info.apy
+        verify_boost(
+            stake_info,
+            pool_info,
+            ctx.accounts.boost_lock.as_ref(),
+            ctx.accounts.boost_token_account.as_ref(),
+        )?;
+
+        // Rewards are paid in reward_mint, so the stake is expressed in its base units first
+        let reward_rate = pool_info.to_reward_units(stake_info.staked_amount)?
+            * pool_info.apy
             / pool_info.apy_denominator
             / constants::SLOTS_PER_YEAR;
 
@@ -351,25 +659,34 @@ pub mod staking_program {
         let claimed_cycles = stake_info.claim_cycles_passed; // Already claimed cycles
         let cycles_passed = (clock.slot - stake_info.last_interaction_time) / reward_cycle_length;
         // Calculate how many additional cycles can be claimed
-        let remaining_cycles = (cycles_passed as u64).min(max_cycles - claimed_cycles);
+        let remaining_cycles = cycles_passed.min(max_cycles - claimed_cycles);
 
         if clock.slot < stake_info.next_claim_time {
             return Err(ErrorCode::Wait.into()); // Not enough time passed for any reward cycle
         }
 
-        // Calculate the total reward for missed cycles
+        // Calculate the total reward for missed cycles, the boost only for the cycles since it was applied
+        let claimable_cycles = 1u64.max(remaining_cycles);
+        let boosted_cycles = stake_info.boosted_cycles(
+            stake_info.last_interaction_time,
+            clock.slot,
+            reward_cycle_length,
+            claimable_cycles,
+        );
         let total_claimable_rewards = stake_info.unclaimed_rewards
-            + (reward_rate_for_cycle * ((1 as u64).max(remaining_cycles as u64)));
+            + (reward_rate_for_cycle * claimable_cycles)
+            + stake_info.boost_reward(reward_rate_for_cycle, boosted_cycles)?;
 
-        if total_claimable_rewards <= 0 {
+        if total_claimable_rewards == 0 {
             // There are not enough claimable rewards
             return Err(ErrorCode::NoReward.into());
         }
 
         // Transfer the total reward to the user
-        let transfer_from_vault_accounts = Transfer {
+        let transfer_from_vault_accounts = TransferChecked {
             from: ctx.accounts.token_vault_account.to_account_info(),
-            to: ctx.accounts.user_token_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.user_reward_token_account.to_account_info(),
             authority: ctx.accounts.token_vault_account.to_account_info(),
         };
 
@@ -379,30 +696,68 @@ pub mod staking_program {
             signer_seeds_for_reward,
         );
 
-        transfer(ctxx, total_claimable_rewards)?;
+        transfer_checked(ctxx, total_claimable_rewards, decimals)?;
+
+        accrue_referral(
+            pool_info,
+            stake_info,
+            ctx.accounts.referrer_wallet_stake.as_deref_mut(),
+            total_claimable_rewards,
+        )?;
 
         // Reset unclaimed rewards and update claim time
         stake_info.total_claimed += total_claimable_rewards;
         if stake_info.unclaimed_rewards >= total_claimable_rewards {
-            stake_info.unclaimed_rewards = stake_info.unclaimed_rewards - total_claimable_rewards;
+            stake_info.unclaimed_rewards -= total_claimable_rewards;
         } else {
             stake_info.unclaimed_rewards = 0;
         }
         stake_info.next_claim_time = stake_info.last_interaction_time
-            + (reward_cycle_length * ((remaining_cycles as u64) + 1)); // Move to the next claim period
+            + (reward_cycle_length * (remaining_cycles + 1)); // Move to the next claim period
         stake_info.last_interaction_time = clock.slot;
         stake_info.claim_cycles_passed += remaining_cycles;
+
+        pay_reward_streams(
+            pool_info,
+            stake_info,
+            ctx.remaining_accounts,
+            &ctx.accounts.signer.key(),
+            clock.slot,
+            ctx.program_id,
+        )?;
+
         stake_info.in_process = false;
         Ok(())
     }
 
-    pub fn restake_rewards(ctx: Context<Reward>, stake_counter: u64) -> Result<()> {
+    pub fn restake_rewards<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Reward<'info>>,
+        _stake_counter: u64,
+    ) -> Result<()> {
         let stake_info = &mut ctx.accounts.stake_info_account;
         let pool_info = &mut ctx.accounts.pool_info;
         let roi_type = pool_info.roi_type;
         let clock = Clock::get()?;
         let bump_for_vault = ctx.bumps.token_vault_account;
-        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[constants::VAULT_SEED, &[bump_for_vault]]];
+        let pool_key = pool_info.key();
+        let reward_mint_key = pool_info.reward_mint;
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::VAULT_SEED,
+            pool_key.as_ref(),
+            reward_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+        let decimals = ctx.accounts.reward_mint.decimals;
+
+        //Emission pools are claimed with claim_emissions
+        if pool_info.reward_mode != constants::REWARD_MODE_APY {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
+
+        //Rewards can only be restaked when they are paid in the staked mint
+        if pool_info.reward_mint != pool_info.token {
+            return Err(ErrorCode::RestakeNotSupported.into());
+        }
 
         // Ensure that the user has staked some tokens before claim
         if !stake_info.is_staked {
@@ -431,7 +786,16 @@ pub mod staking_program {
 
         stake_info.in_process = true;
 
-        let reward_rate = stake_info.staked_amount * pool_info.apy
+        verify_boost(
+            stake_info,
+            pool_info,
+            ctx.accounts.boost_lock.as_ref(),
+            ctx.accounts.boost_token_account.as_ref(),
+        )?;
+
+        // Rewards are paid in reward_mint, so the stake is expressed in its base units first
+        let reward_rate = pool_info.to_reward_units(stake_info.staked_amount)?
+            * pool_info.apy
             / pool_info.apy_denominator
             / constants::SLOTS_PER_YEAR;
 
@@ -456,23 +820,34 @@ pub mod staking_program {
         let claimed_cycles = stake_info.claim_cycles_passed; // Already claimed cycles
         let cycles_passed = (clock.slot - stake_info.last_interaction_time) / reward_cycle_length;
         // Calculate how many additional cycles can be claimed
-        let remaining_cycles = (cycles_passed as u64).min(max_cycles - claimed_cycles);
+        let remaining_cycles = cycles_passed.min(max_cycles - claimed_cycles);
 
         if clock.slot < stake_info.next_claim_time {
             return Err(ErrorCode::Wait.into()); // Not enough time passed for any reward cycle
         }
 
-        // Calculate the total reward for missed cycles
+        // Calculate the total reward for missed cycles, the boost only for the cycles since it was applied
+        let claimable_cycles = 1u64.max(remaining_cycles);
+        let boosted_cycles = stake_info.boosted_cycles(
+            stake_info.last_interaction_time,
+            clock.slot,
+            reward_cycle_length,
+            claimable_cycles,
+        );
         let total_claimable_rewards = stake_info.unclaimed_rewards
-            + (reward_rate_for_cycle * ((1 as u64).max(remaining_cycles as u64)));
+            + (reward_rate_for_cycle * claimable_cycles)
+            + stake_info.boost_reward(reward_rate_for_cycle, boosted_cycles)?;
 
-        if total_claimable_rewards <= 0 {
+        if total_claimable_rewards == 0 {
             return Err(ErrorCode::NoReward.into());
         }
 
+        let balance_before = ctx.accounts.stake_account.amount;
+
         // Transfer the total reward to the user
-        let transfer_from_vault_accounts = Transfer {
+        let transfer_from_vault_accounts = TransferChecked {
             from: ctx.accounts.token_vault_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
             to: ctx.accounts.stake_account.to_account_info(),
             authority: ctx.accounts.token_vault_account.to_account_info(),
         };
@@ -483,18 +858,55 @@ pub mod staking_program {
             signer_seeds_for_reward,
         );
 
-        transfer(ctxx, total_claimable_rewards)?;
+        transfer_checked(ctxx, total_claimable_rewards, decimals)?;
 
-        // Reset unclaimed rewards and update claim time
-        stake_info.staked_amount += total_claimable_rewards;
+        accrue_referral(
+            pool_info,
+            stake_info,
+            ctx.accounts.referrer_wallet_stake.as_deref_mut(),
+            total_claimable_rewards,
+        )?;
+
+        // Only what actually arrived in the stake account is added to the position
+        ctx.accounts.stake_account.reload()?;
+        let received_amount = ctx
+            .accounts
+            .stake_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::InvalidAmount)?;
+
+        // Streams are settled on the old principal before the restaked rewards are added to it,
+        // so a pool with streams needs their accounts here
+        let stream_rewards = stake_info.restake(pool_info, received_amount, clock.slot)?;
+        transfer_stream_rewards(
+            pool_info,
+            &stream_rewards,
+            ctx.remaining_accounts,
+            &ctx.accounts.signer.key(),
+            ctx.program_id,
+        )?;
+
+        // Receipt positions stay 1:1 to their principal, restaked rewards get receipts too
+        if stake_info.receipt_amount > 0 {
+            mint_receipt(
+                pool_info,
+                &ctx.accounts.receipt_mint,
+                &ctx.accounts.user_receipt_token_account,
+                &ctx.accounts.receipt_token_program,
+                &ctx.accounts.signer.key(),
+                received_amount,
+            )?;
+            stake_info.receipt_amount += received_amount;
+        }
         stake_info.total_claimed += total_claimable_rewards;
         if stake_info.unclaimed_rewards >= total_claimable_rewards {
-            stake_info.unclaimed_rewards = stake_info.unclaimed_rewards - total_claimable_rewards;
+            stake_info.unclaimed_rewards -= total_claimable_rewards;
         } else {
             stake_info.unclaimed_rewards = 0;
         }
         stake_info.next_claim_time = stake_info.last_interaction_time
-            + (reward_cycle_length * ((remaining_cycles as u64) + 1)); // Move to the next claim period
+            + (reward_cycle_length * (remaining_cycles + 1)); // Move to the next claim period
         stake_info.last_interaction_time = clock.slot;
         stake_info.claim_cycles_passed += remaining_cycles;
 
@@ -503,46 +915,53 @@ pub mod staking_program {
         Ok(())
     }
 
-    pub fn update_pool_info(
-        ctx: Context<UpdatePoolInfo>,
-        admin: Pubkey,
-        token_vault: Pubkey,
-        lock_time: u64,
-        apy: u64,
-        apy_denominator: u64,
-        roi_type: u64,
-        token: Pubkey,
+    pub fn claim_emissions<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Reward<'info>>,
+        _stake_counter: u64,
     ) -> Result<()> {
-        // Only the current admin (owner) can update the pool_info
-        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
-            return Err(ErrorCode::Unauthorized.into());
-        }
-
-        // Update the pool info
+        let stake_info = &mut ctx.accounts.stake_info_account;
         let pool_info = &mut ctx.accounts.pool_info;
-        pool_info.admin = admin;
-        pool_info.token_vault = token_vault;
-        pool_info.lock_time = lock_time;
-        pool_info.apy = apy;
-        pool_info.apy_denominator = apy_denominator;
-        pool_info.roi_type = roi_type;
-        pool_info.token = token;
+        let clock = Clock::get()?;
+        let bump_for_vault = ctx.bumps.token_vault_account;
+        let pool_key = pool_info.key();
+        let reward_mint_key = pool_info.reward_mint;
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::VAULT_SEED,
+            pool_key.as_ref(),
+            reward_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+        let decimals = ctx.accounts.reward_mint.decimals;
 
-        Ok(())
-    }
+        //Fixed APY pools are claimed with claim_rewards
+        if pool_info.reward_mode != constants::REWARD_MODE_EMISSIONS {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
 
-    pub fn admin_withdraw(ctx: Context<AdminWithdraw>, value: u64) -> Result<()> {
-        // Only the current admin (owner) can withdraw from Treasury
-        if ctx.accounts.signer.key() != ctx.accounts.pool_info.admin {
-            return Err(ErrorCode::Unauthorized.into());
+        //Ensure that the user has staked some tokens before claim
+        if !stake_info.is_staked {
+            return Err(ErrorCode::NotStaked.into());
         }
-        let bump_for_vault = ctx.bumps.token_vault_account;
 
-        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[constants::VAULT_SEED, &[bump_for_vault]]];
+        //Ensure non re-entrance
+        if stake_info.in_process {
+            return Err(ErrorCode::AlreadyInProcess.into());
+        }
+
+        stake_info.in_process = true;
+
+        pool_info.update_emissions(clock.unix_timestamp)?;
+
+        let pending_rewards = pool_info.pending_rewards(stake_info)?;
 
-        let transfer_from_vault_accounts = Transfer {
+        if pending_rewards == 0 {
+            return Err(ErrorCode::NoReward.into());
+        }
+
+        let transfer_from_vault_accounts = TransferChecked {
             from: ctx.accounts.token_vault_account.to_account_info(),
-            to: ctx.accounts.admin_token_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.user_reward_token_account.to_account_info(),
             authority: ctx.accounts.token_vault_account.to_account_info(),
         };
 
@@ -552,199 +971,2186 @@ pub mod staking_program {
             signer_seeds_for_reward,
         );
 
-        transfer(ctxx, value)?;
+        transfer_checked(ctxx, pending_rewards, decimals)?;
+
+        accrue_referral(
+            pool_info,
+            stake_info,
+            ctx.accounts.referrer_wallet_stake.as_deref_mut(),
+            pending_rewards,
+        )?;
+
+        stake_info.reward_debt = pool_info.accrued_rewards(stake_info.staked_amount)?;
+        stake_info.total_claimed += pending_rewards;
+        stake_info.last_interaction_time = clock.slot;
+
+        pay_reward_streams(
+            pool_info,
+            stake_info,
+            ctx.remaining_accounts,
+            &ctx.accounts.signer.key(),
+            clock.slot,
+            ctx.program_id,
+        )?;
+
+        stake_info.in_process = false;
 
         Ok(())
     }
 
-}
+    pub fn fund_emissions(ctx: Context<FundEmissions>, amount: u64) -> Result<()> {
+        // Only the current admin (owner) can top up the emission schedule
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    #[account(mut,address=pubkey!("2EoDxYope1BDUWM6hAWdQ1iwcbEpumiddfTEjuUNF6ud"))]
-    pub signer: Signer<'info>,
-    #[account(mut)]
-    pub admin: UncheckedAccount<'info>,
-    #[account(
-        init_if_needed,
-        seeds = [constants::VAULT_SEED],
-        bump,
-        payer = signer,
-        token::mint = mint ,
-        token::authority= token_vault_account,
-    )]
-    pub token_vault_account: Account<'info, TokenAccount>,
-    #[account(init, payer = signer, space = 8 + std::mem::size_of::<PoolInfo>())]
-    pub pool_info: Account<'info, PoolInfo>,
-    pub mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
-#[account]
-pub struct PoolInfo {
-    pub admin: Pubkey,
-    pub token_vault: Pubkey,
-    pub lock_time: u64,
-    pub apy: u64,
-    pub apy_denominator: u64,
-    pub roi_type: u64,
-    pub token: Pubkey,
-}
+        if ctx.accounts.pool_info.reward_mode != constants::REWARD_MODE_EMISSIONS {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
 
-#[derive(Accounts)]
-#[instruction(stake_counter: u64)]
-pub struct Stake<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+        if amount == 0 {
+            return Err(ErrorCode::NoTokens.into());
+        }
 
-    #[account(
-        init_if_needed,
-        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, signer.key.as_ref(), pool_info.key().as_ref(),  ],
-        bump,
-        payer = signer, 
-        space = 8 + 8 + std::mem::size_of::<StakeInfo>(),
-    )]
-    pub stake_info_account: Account<'info, StakeInfo>,
+        let clock = Clock::get()?;
+        let balance_before = ctx.accounts.token_vault_account.amount;
 
-    #[account(
-        init_if_needed,
-        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
-        bump,
-        payer = signer,
-        token::mint = mint,
-        token::authority = stake_account
-    )]
-    pub stake_account: Account<'info, TokenAccount>,
-    pub pool_info: Account<'info, PoolInfo>,
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.admin_token_account.to_account_info(),
+                    mint: ctx.accounts.reward_mint.to_account_info(),
+                    to: ctx.accounts.token_vault_account.to_account_info(),
+                    authority: ctx.accounts.admin.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.reward_mint.decimals,
+        )?;
 
-    #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = signer,
-    )]
-    pub user_token_account: Account<'info, TokenAccount>,
+        ctx.accounts.token_vault_account.reload()?;
+        let received_amount = ctx
+            .accounts
+            .token_vault_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::InvalidAmount)?;
 
-    pub mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-}
+        let pool_info = &mut ctx.accounts.pool_info;
 
-#[derive(Accounts)]
-#[instruction(stake_counter: u64)]
-pub struct DeStake<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+        pool_info.update_emissions(clock.unix_timestamp)?;
+        // Nothing is emitted for the gap between an ended schedule and now
+        pool_info.last_update_time = clock.unix_timestamp;
 
-    #[account(
+        // The top up keeps the rate and extends the schedule by the time it pays for
+        let extension = (received_amount / pool_info.reward_rate) as i64;
+        pool_info.emission_end = pool_info
+            .emission_end
+            .max(clock.unix_timestamp)
+            .checked_add(extension)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn set_emission_schedule(
+        ctx: Context<UpdatePoolInfo>,
+        reward_rate: u64,
+        emission_end: i64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can change the emission schedule
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let clock = Clock::get()?;
+        let pool_info = &mut ctx.accounts.pool_info;
+
+        if pool_info.reward_mode != constants::REWARD_MODE_EMISSIONS {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
+        if reward_rate == 0 {
+            return Err(ErrorCode::InvalidRewardRate.into());
+        }
+        if emission_end <= clock.unix_timestamp {
+            return Err(ErrorCode::InvalidEmissionEnd.into());
+        }
+
+        // Everything emitted so far is settled at the old rate
+        pool_info.update_emissions(clock.unix_timestamp)?;
+        pool_info.last_update_time = clock.unix_timestamp;
+
+        pool_info.reward_rate = reward_rate;
+        pool_info.emission_end = emission_end;
+
+        Ok(())
+    }
+
+    pub fn set_pool_limits(
+        ctx: Context<UpdatePoolInfo>,
+        max_total_stake: u64,
+        min_stake: u64,
+        max_stake: u64,
+        max_per_wallet: u64,
+        deposit_start: i64,
+        deposit_end: i64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can update the pool limits
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        // 0 disables a limit
+        if min_stake != 0 && max_stake != 0 && min_stake > max_stake {
+            return Err(ErrorCode::InvalidPoolLimits.into());
+        }
+        if deposit_start != 0 && deposit_end != 0 && deposit_start >= deposit_end {
+            return Err(ErrorCode::InvalidPoolLimits.into());
+        }
+
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.max_total_stake = max_total_stake;
+        pool_info.min_stake = min_stake;
+        pool_info.max_stake = max_stake;
+        pool_info.max_per_wallet = max_per_wallet;
+        pool_info.deposit_start = deposit_start;
+        pool_info.deposit_end = deposit_end;
+
+        Ok(())
+    }
+
+    pub fn set_merkle_root(ctx: Context<UpdatePoolInfo>, merkle_root: [u8; 32]) -> Result<()> {
+        // Only the current admin (owner) can change the allowlist
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        // The root is only checked in stake, open positions are not affected by a rotation
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.merkle_root = merkle_root;
+
+        Ok(())
+    }
+
+    pub fn set_referral_bps(ctx: Context<UpdatePoolInfo>, referral_bps: u64) -> Result<()> {
+        // Only the current admin (owner) can change the referral share
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        if referral_bps > constants::BPS_DENOMINATOR {
+            return Err(ErrorCode::InvalidReferralBps.into());
+        }
+
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.referral_bps = referral_bps;
+
+        Ok(())
+    }
+
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferral>) -> Result<()> {
+        let wallet_stake = &mut ctx.accounts.wallet_stake;
+        let bump_for_vault = ctx.bumps.token_vault_account;
+        let pool_key = ctx.accounts.pool_info.key();
+        let reward_mint_key = ctx.accounts.pool_info.reward_mint;
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::VAULT_SEED,
+            pool_key.as_ref(),
+            reward_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+        let decimals = ctx.accounts.reward_mint.decimals;
+
+        let referral_rewards = wallet_stake.referral_rewards;
+
+        if referral_rewards == 0 {
+            return Err(ErrorCode::NoReward.into());
+        }
+
+        // Reset before the transfer, the account is the referrer's own PDA
+        wallet_stake.referral_rewards = 0;
+        wallet_stake.referral_claimed += referral_rewards;
+
+        let transfer_from_vault_accounts = TransferChecked {
+            from: ctx.accounts.token_vault_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.user_reward_token_account.to_account_info(),
+            authority: ctx.accounts.token_vault_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_from_vault_accounts,
+            signer_seeds_for_reward,
+        );
+
+        transfer_checked(ctxx, referral_rewards, decimals)?;
+
+        Ok(())
+    }
+
+    pub fn set_boost(
+        ctx: Context<UpdatePoolInfo>,
+        boost_collection: Pubkey,
+        boost_bps: u64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can configure the NFT boost
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        //Ensure that the boost stays within MAX_BOOST_BPS (at most doubling the APY)
+        if boost_bps > constants::MAX_BOOST_BPS {
+            return Err(ErrorCode::InvalidBoostBps.into());
+        }
+
+        // Positions keep their boost until they are re-checked with refresh_boost
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.boost_collection = boost_collection;
+        pool_info.boost_bps = boost_bps;
+
+        Ok(())
+    }
+
+    pub fn apply_boost(ctx: Context<ApplyBoost>, _stake_counter: u64) -> Result<()> {
+        let stake_info = &mut ctx.accounts.stake_info_account;
+        let pool_info = &ctx.accounts.pool_info;
+
+        //Ensure that the pool has an NFT boost
+        if pool_info.boost_collection == Pubkey::default() || pool_info.boost_bps == 0 {
+            return Err(ErrorCode::BoostNotConfigured.into());
+        }
+
+        //Emission pools share a fixed budget pro rata, the boost only applies to the fixed APY
+        if pool_info.reward_mode != constants::REWARD_MODE_APY {
+            return Err(ErrorCode::InvalidRewardMode.into());
+        }
+
+        //Ensure that the user has staked some tokens
+        if !stake_info.is_staked {
+            return Err(ErrorCode::NotStaked.into());
+        }
+
+        //Ensure non re-entrance
+        if stake_info.in_process {
+            return Err(ErrorCode::AlreadyInProcess.into());
+        }
+
+        //Ensure that the mint is a real NFT, copies of a fungible collection item could boost many positions
+        if ctx.accounts.nft_mint.supply != 1 || ctx.accounts.nft_mint.decimals != 0 {
+            return Err(ErrorCode::InvalidBoostNft.into());
+        }
+
+        // The NFT must belong to the verified collection of the pool
+        let collection_verified = ctx
+            .accounts
+            .nft_metadata
+            .collection
+            .as_ref()
+            .is_some_and(|collection| {
+                collection.verified && collection.key == pool_info.boost_collection
+            });
+
+        if !collection_verified {
+            return Err(ErrorCode::InvalidBoostNft.into());
+        }
+
+        // One NFT boosts one position, moving the lock here ends the boost of the previous position
+        ctx.accounts.boost_lock.position = stake_info.key();
+
+        // The boost only accrues from now on, earlier cycles keep the base APY
+        let clock = Clock::get()?;
+        stake_info.boost_bps = pool_info.boost_bps;
+        stake_info.boost_mint = ctx.accounts.nft_mint.key();
+        stake_info.boost_collection = pool_info.boost_collection;
+        stake_info.boost_start_slot = clock.slot;
+
+        Ok(())
+    }
+
+    pub fn refresh_boost(ctx: Context<RefreshBoost>, _stake_counter: u64) -> Result<()> {
+        let stake_info = &mut ctx.accounts.stake_info_account;
+        let pool_info = &ctx.accounts.pool_info;
+
+        if stake_info.boost_mint == Pubkey::default() {
+            return Err(ErrorCode::BoostNotApplied.into());
+        }
+
+        //Ensure non re-entrance
+        if stake_info.in_process {
+            return Err(ErrorCode::AlreadyInProcess.into());
+        }
+
+        // Anyone can re-check a position, the NFT is expected in the owner's associated token account
+        verify_boost(
+            stake_info,
+            pool_info,
+            Some(&ctx.accounts.boost_lock),
+            Some(&ctx.accounts.nft_token_account),
+        )
+    }
+
+    pub fn enable_receipt_token(ctx: Context<EnableReceiptToken>) -> Result<()> {
+        // Only the current admin (owner) can turn the pool into a receipt pool
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        // Every later stake mints receipts of the pool's mint. Positions opened before keep
+        // working without receipts
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.receipts_enabled = true;
+        pool_info.receipt_mint = ctx.accounts.receipt_mint.key();
+        pool_info.receipt_bump = ctx.bumps.receipt_mint;
+
+        Ok(())
+    }
+
+    pub fn redeem_receipt<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RedeemReceipt<'info>>,
+        _stake_counter: u64,
+    ) -> Result<()> {
+        let stake_info = &mut ctx.accounts.stake_info_account;
+        let pool_info = &mut ctx.accounts.pool_info;
+
+        let clock = Clock::get()?;
+
+        stake_info.assert_redeemable(clock.slot)?;
+
+        stake_info.in_process = true;
+
+        //Burn receipt_amount receipt tokens of the pool, the token program fails if the signer holds less.
+        //Receipts are fungible, any of them redeem any unlocked receipt position of the pool
+        let burn_receipt_accounts = Burn {
+            mint: ctx.accounts.receipt_mint.to_account_info(),
+            from: ctx.accounts.user_receipt_token_account.to_account_info(),
+            authority: ctx.accounts.signer.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new(
+            ctx.accounts.receipt_token_program.to_account_info(),
+            burn_receipt_accounts,
+        );
+
+        burn(ctxx, stake_info.receipt_amount)?;
+
+        // The receipt carries the principal only, rewards of the position go to its owner
+        let stake_amount = stake_info.staked_amount;
+        let decimals = ctx.accounts.mint.decimals;
+
+        verify_boost(
+            stake_info,
+            pool_info,
+            ctx.accounts.boost_lock.as_ref(),
+            ctx.accounts.boost_token_account.as_ref(),
+        )?;
+
+        settle_position_rewards(
+            pool_info,
+            stake_info,
+            &ctx.accounts.token_vault_account,
+            &ctx.accounts.reward_mint,
+            &ctx.accounts.owner_reward_token_account,
+            &ctx.accounts.reward_token_program,
+            ctx.accounts.referrer_wallet_stake.as_deref_mut(),
+        )?;
+
+        pool_info.total_staked = pool_info.total_staked.saturating_sub(stake_info.staked_amount);
+
+        let wallet_stake = &mut ctx.accounts.wallet_stake;
+        wallet_stake.total_staked = wallet_stake
+            .total_staked
+            .saturating_sub(stake_info.deposited_amount);
+
+        // Stream rewards are paid to the owner's stream token accounts
+        let owner = ctx.accounts.owner.key();
+        pay_reward_streams(
+            pool_info,
+            stake_info,
+            ctx.remaining_accounts,
+            &owner,
+            clock.slot,
+            ctx.program_id,
+        )?;
+
+        let poolkey = pool_info.key();
+
+        let bump_for_stake_account = ctx.bumps.stake_account;
+
+        let signer_seeds_for_user_stake: &[&[&[u8]]] = &[&[
+            constants::TOKEN_SEED,
+            owner.as_ref(),
+            poolkey.as_ref(),
+            &[bump_for_stake_account],
+        ]];
+
+        //transfer the principal of this position from the owner's stake account to the redeemer
+
+        let transfer_from_stake_accounts = TransferChecked {
+            from: ctx.accounts.stake_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.user_token_account.to_account_info(),
+            authority: ctx.accounts.stake_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_from_stake_accounts,
+            signer_seeds_for_user_stake,
+        );
+
+        transfer_checked(ctxx, stake_amount, decimals)?;
+
+        //update the states
+
+        stake_info.staked_amount = 0;
+        stake_info.is_staked = false;
+        stake_info.end_time = 0;
+        stake_info.unclaimed_rewards = 0;
+        stake_info.total_claimed = 0;
+        stake_info.last_interaction_time = clock.slot;
+        stake_info.next_claim_time = 0;
+        stake_info.reward_debt = 0;
+        stake_info.deposited_amount = 0;
+        stake_info.receipt_amount = 0;
+        stake_info.stream_last_slot = [0; constants::MAX_REWARD_STREAMS];
+        stake_info.stream_claimed = [0; constants::MAX_REWARD_STREAMS];
+
+        stake_info.in_process = false;
+
+        Ok(())
+    }
+
+    pub fn set_position_transfers(ctx: Context<UpdatePoolInfo>, enabled: bool) -> Result<()> {
+        // Only the current admin (owner) can allow or forbid position transfers
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.transfers_enabled = enabled;
+
+        let clock = Clock::get()?;
+
+        emit!(PositionTransfersChanged {
+            pool: pool_info.key(),
+            admin: ctx.accounts.admin.key(),
+            enabled,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn transfer_position(ctx: Context<TransferPosition>, _stake_counter: u64) -> Result<()> {
+        let stake_info = &mut ctx.accounts.stake_info_account;
+        let pool_info = &ctx.accounts.pool_info;
+
+        //Ensure that the pool allows position transfers
+        if !pool_info.transfers_enabled {
+            return Err(ErrorCode::PositionTransfersDisabled.into());
+        }
+
+        //Ensure that the stake record exists
+        if !stake_info.is_staked {
+            return Err(ErrorCode::NotStaked.into());
+        }
+
+        //Ensure that the position goes to another wallet
+        if ctx.accounts.new_owner.key() == ctx.accounts.signer.key() {
+            return Err(ErrorCode::InvalidNewOwner.into());
+        }
+
+        //Ensure that the position has no receipt tokens, they redeem the principal independent of the owner
+        if stake_info.receipt_amount > 0 {
+            return Err(ErrorCode::ReceiptPositionNotTransferable.into());
+        }
+
+        //Ensure non re-entrance
+        if stake_info.in_process {
+            return Err(ErrorCode::AlreadyInProcess.into());
+        }
+
+        stake_info.in_process = true;
+
+        let staker = ctx.accounts.signer.key();
+        let poolkey = pool_info.key();
+
+        let bump_for_stake_account = ctx.bumps.stake_account;
+
+        let signer_seeds_for_user_stake: &[&[&[u8]]] = &[&[
+            constants::TOKEN_SEED,
+            staker.as_ref(),
+            poolkey.as_ref(),
+            &[bump_for_stake_account],
+        ]];
+
+        let balance_before = ctx.accounts.new_owner_stake_account.amount;
+
+        //Move the principal into the new owner's stake account, custody follows the owner
+
+        let transfer_between_stake_accounts = TransferChecked {
+            from: ctx.accounts.stake_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.new_owner_stake_account.to_account_info(),
+            authority: ctx.accounts.stake_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_between_stake_accounts,
+            signer_seeds_for_user_stake,
+        );
+
+        transfer_checked(ctxx, stake_info.staked_amount, ctx.accounts.mint.decimals)?;
+
+        // A transfer fee would shrink the position, its rewards and receipts no longer match then
+        ctx.accounts.new_owner_stake_account.reload()?;
+        let received_amount = ctx
+            .accounts
+            .new_owner_stake_account
+            .amount
+            .checked_sub(balance_before)
+            .ok_or(ErrorCode::InvalidAmount)?;
+
+        if received_amount != stake_info.staked_amount {
+            return Err(ErrorCode::TransferFeeNotSupported.into());
+        }
+
+        // The deposit counts towards the wallet limit of the new owner from now on
+        let wallet_stake = &mut ctx.accounts.wallet_stake;
+        wallet_stake.total_staked = wallet_stake
+            .total_staked
+            .saturating_sub(stake_info.deposited_amount);
+
+        let new_owner_wallet_stake = &mut ctx.accounts.new_owner_wallet_stake;
+        new_owner_wallet_stake.total_staked = new_owner_wallet_stake
+            .total_staked
+            .checked_add(stake_info.deposited_amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        if pool_info.max_per_wallet != 0
+            && new_owner_wallet_stake.total_staked > pool_info.max_per_wallet
+        {
+            return Err(ErrorCode::WalletCapExceeded.into());
+        }
+
+        // Rewards are paid to the owner, so the reward destination moves with it
+        stake_info.owner = ctx.accounts.new_owner.key();
+
+        // The boost was earned with the previous owner's NFT, the new owner applies their own
+        stake_info.boost_bps = 0;
+        stake_info.boost_mint = Pubkey::default();
+        stake_info.boost_collection = Pubkey::default();
+        stake_info.boost_start_slot = 0;
+
+        stake_info.in_process = false;
+
+        let clock = Clock::get()?;
+
+        emit!(PositionTransferred {
+            pool: poolkey,
+            position: stake_info.key(),
+            from: staker,
+            to: stake_info.owner,
+            staked_amount: stake_info.staked_amount,
+            timestamp: clock.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    pub fn update_pool_info(
+        ctx: Context<UpdatePoolInfo>,
+        admin: Pubkey,
+        token_vault: Pubkey,
+        lock_time: u64,
+        apy: u64,
+        apy_denominator: u64,
+        roi_type: u64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can update the pool_info
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        // Update the pool info
+        let pool_info = &mut ctx.accounts.pool_info;
+        pool_info.admin = admin;
+        pool_info.token_vault = token_vault;
+        pool_info.lock_time = lock_time;
+        pool_info.apy = apy;
+        pool_info.apy_denominator = apy_denominator;
+        pool_info.roi_type = roi_type;
+
+        Ok(())
+    }
+
+    pub fn admin_withdraw(ctx: Context<AdminWithdraw>, value: u64) -> Result<()> {
+        // Only the current admin (owner) can withdraw from Treasury
+        if ctx.accounts.signer.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        let bump_for_vault = ctx.bumps.token_vault_account;
+        let decimals = ctx.accounts.reward_mint.decimals;
+        let pool_key = ctx.accounts.pool_info.key();
+        let reward_mint_key = ctx.accounts.pool_info.reward_mint;
+
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::VAULT_SEED,
+            pool_key.as_ref(),
+            reward_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+
+        let transfer_from_vault_accounts = TransferChecked {
+            from: ctx.accounts.token_vault_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.admin_token_account.to_account_info(),
+            authority: ctx.accounts.token_vault_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_from_vault_accounts,
+            signer_seeds_for_reward,
+        );
+
+        transfer_checked(ctxx, value, decimals)?;
+
+        Ok(())
+    }
+
+    pub fn add_reward_stream(
+        ctx: Context<AddRewardStream>,
+        apy: u64,
+        apy_denominator: u64,
+        start_slot: u64,
+        end_slot: u64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can add reward streams
+        if ctx.accounts.admin.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        if apy == 0 {
+            return Err(ErrorCode::InvalidApy.into());
+        }
+        if apy_denominator == 0 {
+            return Err(ErrorCode::InvalidApyDenominator.into());
+        }
+        if end_slot <= start_slot {
+            return Err(ErrorCode::InvalidStreamPeriod.into());
+        }
+
+        assert_supported_mint(&ctx.accounts.stream_mint.to_account_info())?;
+
+        let pool_info = &mut ctx.accounts.pool_info;
+        let index = pool_info.reward_stream_count as usize;
+
+        if index >= constants::MAX_REWARD_STREAMS {
+            return Err(ErrorCode::TooManyRewardStreams.into());
+        }
+
+        pool_info.reward_streams[index] = RewardStream {
+            mint: ctx.accounts.stream_mint.key(),
+            vault: ctx.accounts.token_vault_account.key(),
+            apy,
+            apy_denominator,
+            start_slot,
+            end_slot,
+            decimals: ctx.accounts.stream_mint.decimals,
+        };
+        pool_info.reward_stream_count += 1;
+
+        Ok(())
+    }
+
+    pub fn admin_withdraw_stream(
+        ctx: Context<AdminWithdrawStream>,
+        _stream_index: u8,
+        value: u64,
+    ) -> Result<()> {
+        // Only the current admin (owner) can withdraw from Treasury
+        if ctx.accounts.signer.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        let stream_mint_key = ctx.accounts.stream_mint.key();
+        let poolkey = ctx.accounts.pool_info.key();
+        let bump_for_vault = ctx.bumps.token_vault_account;
+        let decimals = ctx.accounts.stream_mint.decimals;
+
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::STREAM_VAULT_SEED,
+            poolkey.as_ref(),
+            stream_mint_key.as_ref(),
+            &[bump_for_vault],
+        ]];
+
+        let transfer_from_vault_accounts = TransferChecked {
+            from: ctx.accounts.token_vault_account.to_account_info(),
+            mint: ctx.accounts.stream_mint.to_account_info(),
+            to: ctx.accounts.admin_token_account.to_account_info(),
+            authority: ctx.accounts.token_vault_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_from_vault_accounts,
+            signer_seeds_for_reward,
+        );
+
+        transfer_checked(ctxx, value, decimals)?;
+
+        Ok(())
+    }
+
+    pub fn migrate_legacy_vault(ctx: Context<MigrateLegacyVault>) -> Result<()> {
+        // Only the current admin (owner) can migrate the Treasury
+        if ctx.accounts.signer.key() != ctx.accounts.pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        // Rewards funded before the vault was keyed by the reward mint sit in the vault with
+        // the bare b"vault" seed, nothing pays from it anymore
+        let amount = ctx.accounts.legacy_vault_account.amount;
+
+        //Ensure that there is something to migrate
+        if amount == 0 {
+            return Err(ErrorCode::InvalidAmount.into());
+        }
+
+        let bump_for_legacy_vault = ctx.bumps.legacy_vault_account;
+        let decimals = ctx.accounts.reward_mint.decimals;
+
+        let signer_seeds_for_legacy_vault: &[&[&[u8]]] =
+            &[&[constants::VAULT_SEED, &[bump_for_legacy_vault]]];
+
+        //Move the whole balance into the reward vault of the pool
+
+        let transfer_between_vaults = TransferChecked {
+            from: ctx.accounts.legacy_vault_account.to_account_info(),
+            mint: ctx.accounts.reward_mint.to_account_info(),
+            to: ctx.accounts.token_vault_account.to_account_info(),
+            authority: ctx.accounts.legacy_vault_account.to_account_info(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            transfer_between_vaults,
+            signer_seeds_for_legacy_vault,
+        );
+
+        transfer_checked(ctxx, amount, decimals)?;
+
+        Ok(())
+    }
+
+    pub fn migrate_pool_info(ctx: Context<MigratePoolInfo>) -> Result<()> {
+        let mut pool_info: PoolInfo = realloc_legacy_account(
+            &ctx.accounts.pool_info,
+            &ctx.accounts.signer,
+            &ctx.accounts.system_program,
+            8 + std::mem::size_of::<PoolInfo>(),
+        )?;
+
+        // Only the current admin (owner) can migrate the pool
+        if ctx.accounts.signer.key() != pool_info.admin {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+
+        //Ensure that the pool was written with an older layout
+        if pool_info.version >= constants::LAYOUT_VERSION {
+            return Err(ErrorCode::AlreadyMigrated.into());
+        }
+
+        //Ensure that the decimals are read from the staked mint
+        if ctx.accounts.mint.key() != pool_info.token {
+            return Err(ErrorCode::InvalidMint.into());
+        }
+
+        // Legacy pools paid a fixed APY in the staked mint
+        pool_info.reward_mint = pool_info.token;
+        pool_info.token_decimals = ctx.accounts.mint.decimals;
+        pool_info.reward_decimals = ctx.accounts.mint.decimals;
+        pool_info.reward_mode = constants::REWARD_MODE_APY;
+        pool_info.version = constants::LAYOUT_VERSION;
+
+        let mut data = ctx.accounts.pool_info.try_borrow_mut_data()?;
+        pool_info.try_serialize(&mut &mut data[..])
+    }
+
+    pub fn migrate_stake_info(ctx: Context<MigrateStakeInfo>, _stake_counter: u64) -> Result<()> {
+        let mut stake_info: StakeInfo = realloc_legacy_account(
+            &ctx.accounts.stake_info_account,
+            &ctx.accounts.signer,
+            &ctx.accounts.system_program,
+            8 + 8 + std::mem::size_of::<StakeInfo>(),
+        )?;
+
+        //Ensure that the position was written with an older layout
+        if stake_info.version >= constants::LAYOUT_VERSION {
+            return Err(ErrorCode::AlreadyMigrated.into());
+        }
+
+        // The position address is derived from the signer, so the signer staked it. Its deposit
+        // was never counted in a WalletStake, deposited_amount stays 0
+        stake_info.owner = ctx.accounts.signer.key();
+        stake_info.staker = ctx.accounts.signer.key();
+        stake_info.version = constants::LAYOUT_VERSION;
+
+        let mut data = ctx.accounts.stake_info_account.try_borrow_mut_data()?;
+        stake_info.try_serialize(&mut &mut data[..])
+    }
+}
+
+/// Grows an account written with an older layout of `T` to `space` bytes and loads it. Fields
+/// are only ever appended to the layouts, after the realloc they read as zero / default
+fn realloc_legacy_account<'info, T: AccountDeserialize>(
+    account: &AccountInfo<'info>,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+    space: usize,
+) -> Result<T> {
+    if account.data_len() < space {
+        let lamports_needed = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(account.lamports());
+
+        if lamports_needed > 0 {
+            let transfer_rent_accounts = anchor_lang::system_program::Transfer {
+                from: payer.to_account_info(),
+                to: account.clone(),
+            };
+
+            let ctxx = CpiContext::new(system_program.to_account_info(), transfer_rent_accounts);
+
+            anchor_lang::system_program::transfer(ctxx, lamports_needed)?;
+        }
+
+        account.realloc(space, true)?;
+    }
+
+    // Fails for accounts of another type, the discriminator is checked
+    T::try_deserialize(&mut &account.try_borrow_data()?[..])
+}
+
+/// Pays what every reward stream accrued since the last payout, pro rata per slot, in the same
+/// instruction as the base reward. Empty remaining_accounts skip the streams.
+/// remaining_accounts: `[vault, mint, user_token_account, token_program]` for each stream, in order
+fn pay_reward_streams<'info>(
+    pool_info: &Account<'_, PoolInfo>,
+    stake_info: &mut StakeInfo,
+    remaining_accounts: &'info [AccountInfo<'info>],
+    user: &Pubkey,
+    slot: u64,
+    program_id: &Pubkey,
+) -> Result<()> {
+    if remaining_accounts.is_empty() {
+        return Ok(());
+    }
+
+    let rewards = stake_info.settle_streams(pool_info, slot)?;
+    transfer_stream_rewards(pool_info, &rewards, remaining_accounts, user, program_id)
+}
+
+/// Transfers settled stream `rewards` from the stream vaults to `user`.
+/// remaining_accounts: `[vault, mint, user_token_account, token_program]` for each stream, in order
+fn transfer_stream_rewards<'info>(
+    pool_info: &Account<'_, PoolInfo>,
+    rewards: &[u64; constants::MAX_REWARD_STREAMS],
+    remaining_accounts: &'info [AccountInfo<'info>],
+    user: &Pubkey,
+    program_id: &Pubkey,
+) -> Result<()> {
+    let stream_count = pool_info.reward_stream_count as usize;
+    let pool_key = pool_info.key();
+
+    if remaining_accounts.len() != stream_count * 4 {
+        return Err(ErrorCode::InvalidRemainingAccounts.into());
+    }
+
+    for (index, accounts) in remaining_accounts.chunks(4).enumerate() {
+        let stream = &pool_info.reward_streams[index];
+        let (vault, mint, user_token_account, token_program) =
+            (&accounts[0], &accounts[1], &accounts[2], &accounts[3]);
+
+        let (expected_vault, bump_for_vault) = Pubkey::find_program_address(
+            &[constants::STREAM_VAULT_SEED, pool_key.as_ref(), stream.mint.as_ref()],
+            program_id,
+        );
+
+        // The vault signs the CPI, so it may only be handed to a real token program
+        if vault.key() != expected_vault
+            || mint.key() != stream.mint
+            || *mint.owner != token_program.key()
+            || (token_program.key() != anchor_spl::token::ID
+                && token_program.key() != spl_token_2022::ID)
+        {
+            return Err(ErrorCode::InvalidRemainingAccounts.into());
+        }
+
+        let user_stream_account = InterfaceAccount::<TokenAccount>::try_from(user_token_account)?;
+        if user_stream_account.owner != *user || user_stream_account.mint != stream.mint {
+            return Err(ErrorCode::InvalidRemainingAccounts.into());
+        }
+
+        if rewards[index] == 0 {
+            continue;
+        }
+
+        let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+            constants::STREAM_VAULT_SEED,
+            pool_key.as_ref(),
+            stream.mint.as_ref(),
+            &[bump_for_vault],
+        ]];
+
+        let transfer_from_vault_accounts = TransferChecked {
+            from: vault.clone(),
+            mint: mint.clone(),
+            to: user_token_account.clone(),
+            authority: vault.clone(),
+        };
+
+        let ctxx = CpiContext::new_with_signer(
+            token_program.clone(),
+            transfer_from_vault_accounts,
+            signer_seeds_for_reward,
+        );
+
+        transfer_checked(ctxx, rewards[index], stream.decimals)?;
+    }
+
+    Ok(())
+}
+
+/// Whether `owner` still holds the boost NFT in `token_account`, which has to be the owner's
+/// associated token account of the NFT (derived for both token programs, a closed account no
+/// longer tells which one it was)
+fn holds_boost_nft(owner: &Pubkey, boost_mint: &Pubkey, token_account: &AccountInfo) -> Result<bool> {
+    let is_owner_ata = [anchor_spl::token::ID, spl_token_2022::ID]
+        .iter()
+        .any(|token_program_id| {
+            token_account.key()
+                == get_associated_token_address_with_program_id(owner, boost_mint, token_program_id)
+        });
+
+    if !is_owner_ata {
+        return Err(ErrorCode::InvalidBoostNft.into());
+    }
+
+    // Read straight from the account data, it may be closed or owned by the system program by now
+    if token_account.data_is_empty()
+        || (*token_account.owner != anchor_spl::token::ID
+            && *token_account.owner != spl_token_2022::ID)
+    {
+        return Ok(false);
+    }
+
+    let token_account_data = token_account.try_borrow_data()?;
+    Ok(TokenAccount::try_deserialize(&mut &token_account_data[..])
+        .is_ok_and(|nft_token_account| {
+            nft_token_account.owner == *owner && nft_token_account.amount > 0
+        }))
+}
+
+/// Only a boost still backed by the NFT pays out. Drops the boost of a position unless the owner
+/// still holds the NFT, its boost lock still points at the position and the pool did not change
+/// its collection. A lower pool boost applies right away, a higher one needs apply_boost
+fn verify_boost<'info>(
+    stake_info: &mut Account<'info, StakeInfo>,
+    pool_info: &PoolInfo,
+    boost_lock: Option<&Account<'info, BoostLock>>,
+    boost_token_account: Option<&UncheckedAccount<'info>>,
+) -> Result<()> {
+    if stake_info.boost_mint == Pubkey::default() {
+        return Ok(());
+    }
+
+    let still_held = match (boost_lock, boost_token_account) {
+        (Some(boost_lock), Some(boost_token_account)) => {
+            boost_lock.position == stake_info.key()
+                && holds_boost_nft(&stake_info.owner, &stake_info.boost_mint, boost_token_account)?
+        }
+        _ => false,
+    };
+
+    if !still_held || stake_info.boost_collection != pool_info.boost_collection {
+        stake_info.boost_bps = 0;
+        stake_info.boost_mint = Pubkey::default();
+        stake_info.boost_collection = Pubkey::default();
+        stake_info.boost_start_slot = 0;
+        msg!("Boost removed");
+    } else {
+        stake_info.boost_bps = stake_info.boost_bps.min(pool_info.boost_bps);
+    }
+
+    Ok(())
+}
+
+/// Mints `amount` receipt tokens of the pool to the staker, the receipt mint signs for itself
+fn mint_receipt<'info>(
+    pool_info: &Account<'info, PoolInfo>,
+    receipt_mint: &Option<InterfaceAccount<'info, Mint>>,
+    user_receipt_token_account: &Option<InterfaceAccount<'info, TokenAccount>>,
+    receipt_token_program: &Option<Interface<'info, TokenInterface>>,
+    user: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let (receipt_mint, user_receipt_token_account, receipt_token_program) =
+        match (receipt_mint, user_receipt_token_account, receipt_token_program) {
+            (Some(receipt_mint), Some(user_receipt_token_account), Some(receipt_token_program)) => {
+                (receipt_mint, user_receipt_token_account, receipt_token_program)
+            }
+            _ => return Err(ErrorCode::InvalidReceiptAccounts.into()),
+        };
+
+    if receipt_mint.key() != pool_info.receipt_mint
+        || user_receipt_token_account.mint != receipt_mint.key()
+        || user_receipt_token_account.owner != *user
+    {
+        return Err(ErrorCode::InvalidReceiptAccounts.into());
+    }
+
+    let pool_key = pool_info.key();
+    let signer_seeds_for_receipt: &[&[&[u8]]] = &[&[
+        constants::RECEIPT_MINT_SEED,
+        pool_key.as_ref(),
+        &[pool_info.receipt_bump],
+    ]];
+
+    let mint_receipt_accounts = MintTo {
+        mint: receipt_mint.to_account_info(),
+        to: user_receipt_token_account.to_account_info(),
+        authority: receipt_mint.to_account_info(),
+    };
+
+    let ctxx = CpiContext::new_with_signer(
+        receipt_token_program.to_account_info(),
+        mint_receipt_accounts,
+        signer_seeds_for_receipt,
+    );
+
+    mint_to(ctxx, amount)
+}
+
+/// Pays what a position that is being closed still has outstanding, the compounded autostake
+/// reward and the pending emissions, from the reward vault of the pool to `to` and credits the
+/// referrer with its share
+fn settle_position_rewards<'info>(
+    pool_info: &mut Account<'info, PoolInfo>,
+    stake_info: &StakeInfo,
+    token_vault_account: &InterfaceAccount<'info, TokenAccount>,
+    reward_mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    reward_token_program: &Interface<'info, TokenInterface>,
+    referrer_wallet_stake: Option<&mut WalletStake>,
+) -> Result<()> {
+    let clock = Clock::get()?;
+    let (autostake_reward, pending_rewards) =
+        pool_info.closing_rewards(stake_info, clock.unix_timestamp)?;
+
+    // Capped at the vault balance, an underfunded vault must never block closing a position
+    let pending_rewards =
+        pending_rewards.min(token_vault_account.amount.saturating_sub(autostake_reward));
+    let total_reward = autostake_reward + pending_rewards;
+
+    if total_reward == 0 {
+        return Ok(());
+    }
+
+    let pool_key = pool_info.key();
+    let reward_mint_key = pool_info.reward_mint;
+    let (_, bump_for_vault) = Pubkey::find_program_address(
+        &[constants::VAULT_SEED, pool_key.as_ref(), reward_mint_key.as_ref()],
+        &crate::ID,
+    );
+
+    let signer_seeds_for_reward: &[&[&[u8]]] = &[&[
+        constants::VAULT_SEED,
+        pool_key.as_ref(),
+        reward_mint_key.as_ref(),
+        &[bump_for_vault],
+    ]];
+
+    let transfer_from_vault_accounts = TransferChecked {
+        from: token_vault_account.to_account_info(),
+        mint: reward_mint.to_account_info(),
+        to: to.to_account_info(),
+        authority: token_vault_account.to_account_info(),
+    };
+
+    let ctxx = CpiContext::new_with_signer(
+        reward_token_program.to_account_info(),
+        transfer_from_vault_accounts,
+        signer_seeds_for_reward,
+    );
+
+    transfer_checked(ctxx, total_reward, reward_mint.decimals)?;
+
+    accrue_referral(pool_info, stake_info, referrer_wallet_stake, total_reward)
+}
+
+/// Credits the referrer of a position with `referral_bps` of `reward`, on top of the staker's reward
+fn accrue_referral(
+    pool_info: &PoolInfo,
+    stake_info: &StakeInfo,
+    referrer_wallet_stake: Option<&mut WalletStake>,
+    reward: u64,
+) -> Result<()> {
+    if stake_info.referrer == Pubkey::default() || pool_info.referral_bps == 0 || reward == 0 {
+        return Ok(());
+    }
+
+    let referrer_wallet_stake = referrer_wallet_stake.ok_or(ErrorCode::InvalidReferrer)?;
+
+    if referrer_wallet_stake.wallet != stake_info.referrer
+        || referrer_wallet_stake.pool != stake_info.pool_info
+    {
+        return Err(ErrorCode::InvalidReferrer.into());
+    }
+
+    let referral_share = (reward as u128 * pool_info.referral_bps as u128
+        / constants::BPS_DENOMINATOR as u128) as u64;
+
+    referrer_wallet_stake.referral_rewards = referrer_wallet_stake
+        .referral_rewards
+        .checked_add(referral_share)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    Ok(())
+}
+
+/// Sorted pair keccak Merkle proof, leaves are `keccak(wallet || max_allowed as u64 little endian)`
+fn verify_merkle_proof(proof: &[[u8; 32]], root: [u8; 32], leaf: [u8; 32]) -> bool {
+    let computed = proof.iter().fold(leaf, |node, sibling| {
+        if node <= *sibling {
+            keccak::hashv(&[&node, sibling]).0
+        } else {
+            keccak::hashv(&[sibling, &node]).0
+        }
+    });
+
+    computed == root
+}
+
+/// Rejects Token-2022 mints with extensions that could move, freeze or block pool funds.
+/// Every mint a pool holds (staked, reward and stream mints) goes through it
+fn assert_supported_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+
+    let mint_data = mint.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    for extension in mint_state.get_extension_types()? {
+        match extension {
+            ExtensionType::TransferFeeConfig
+            | ExtensionType::MetadataPointer
+            | ExtensionType::TokenMetadata
+            | ExtensionType::InterestBearingConfig => {}
+            _ => return Err(ErrorCode::UnsupportedMintExtension.into()),
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    #[account(mut,address=pubkey!("2EoDxYope1BDUWM6hAWdQ1iwcbEpumiddfTEjuUNF6ud"))]
+    pub signer: Signer<'info>,
+    #[account(mut)]
+    pub admin: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), reward_mint.key().as_ref()],
+        bump,
+        payer = signer,
+        token::mint = reward_mint ,
+        token::authority= token_vault_account,
+        token::token_program = reward_token_program,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(init, payer = signer, space = 8 + std::mem::size_of::<PoolInfo>())]
+    pub pool_info: Account<'info, PoolInfo>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub reward_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut,address=pubkey!("2EoDxYope1BDUWM6hAWdQ1iwcbEpumiddfTEjuUNF6ud"))]
+    pub signer: Signer<'info>,
+    #[account(
+        init,
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        payer = signer,
+        space = 8 + std::mem::size_of::<Config>(),
+    )]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(mut)]
+    pub guardian: Signer<'info>,
+    #[account(mut, seeds = [constants::CONFIG_SEED], bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[account]
+pub struct Config {
+    pub guardian: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct PauseStatusChanged {
+    pub guardian: Pubkey,
+    pub paused: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionTransfersChanged {
+    pub pool: Pubkey,
+    pub admin: Pubkey,
+    pub enabled: bool,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionTransferred {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub staked_amount: u64,
+    pub timestamp: i64,
+}
+
+#[account]
+#[derive(Default)]
+pub struct PoolInfo {
+    pub admin: Pubkey,
+    pub token_vault: Pubkey,
+    pub lock_time: u64,
+    pub apy: u64,
+    pub apy_denominator: u64,
+    pub roi_type: u64,
+    pub token: Pubkey,
+    pub reward_mint: Pubkey,
+    pub token_decimals: u8,
+    pub reward_decimals: u8,
+    pub reward_stream_count: u8,
+    pub reward_streams: [RewardStream; constants::MAX_REWARD_STREAMS],
+    pub reward_mode: u8,               // REWARD_MODE_APY or REWARD_MODE_EMISSIONS
+    pub total_staked: u64,             // sum of staked_amount over all open positions
+    pub reward_rate: u64,              // emissions: reward_mint base units per second
+    pub emission_end: i64,             // emissions: unix timestamp the schedule runs until
+    pub last_update_time: i64,         // emissions: timestamp acc_reward_per_share is settled up to
+    pub acc_reward_per_share: u128,    // emissions: rewards per staked base unit, scaled by ACC_PRECISION
+    pub max_total_stake: u64,          // cap on total_staked, 0 = no limit
+    pub min_stake: u64,                // minimum staked base units per position, 0 = no limit
+    pub max_stake: u64,                // maximum staked base units per position, 0 = no limit
+    pub max_per_wallet: u64,           // maximum deposits of one wallet over all positions, 0 = no limit
+    pub deposit_start: i64,            // unix timestamp deposits open, 0 = open since initialize
+    pub deposit_end: i64,              // unix timestamp deposits close, 0 = never
+    pub merkle_root: [u8; 32],         // allowlist root, all zero = open pool
+    pub referral_bps: u64,             // share of rewards credited to the referrer, 0 = no referrals
+    pub boost_collection: Pubkey,      // verified Metaplex collection of the NFT boost, default = none
+    pub boost_bps: u64,                // APY boost for holders of the collection, in basis points
+    pub receipts_enabled: bool,        // every new position mints receipt tokens of the pool
+    pub transfers_enabled: bool,       // positions can be handed to another wallet with transfer_position
+    pub receipt_mint: Pubkey,          // liquid staking receipt mint of the pool, default = none
+    pub receipt_bump: u8,              // bump of the receipt mint PDA
+    pub version: u8,                   // LAYOUT_VERSION, 0 = legacy pool that needs migrate_pool_info
+}
+
+#[account]
+pub struct WalletStake {
+    pub total_staked: u64,     // deposits of a wallet into a pool over all open positions
+    pub wallet: Pubkey,        // set at the first stake of the wallet in the pool
+    pub pool: Pubkey,
+    pub referrer: Pubkey,      // bound at the first stake, default = no referrer
+    pub referral_count: u64,   // wallets referred by this wallet
+    pub referral_rewards: u64, // referral rewards accrued and not claimed yet
+    pub referral_claimed: u64, // referral rewards claimed so far
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct RewardStream {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub apy: u64,
+    pub apy_denominator: u64,
+    pub start_slot: u64,
+    pub end_slot: u64,
+    pub decimals: u8,
+}
+
+impl PoolInfo {
+    /// Converts an amount of the staked mint into base units of the reward mint
+    pub fn to_reward_units(&self, amount: u64) -> Result<u64> {
+        self.to_units(amount, self.reward_decimals)
+    }
+
+    /// Converts an amount of the staked mint into base units of a mint with `decimals`
+    pub fn to_units(&self, amount: u64, decimals: u8) -> Result<u64> {
+        let converted = if decimals >= self.token_decimals {
+            (amount as u128).checked_mul(10u128.pow((decimals - self.token_decimals) as u32))
+        } else {
+            (amount as u128).checked_div(10u128.pow((self.token_decimals - decimals) as u32))
+        };
+
+        converted
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    /// APY of a position including its NFT boost
+    pub fn effective_apy(&self, boost_bps: u64) -> Result<u64> {
+        let boost = self
+            .apy
+            .checked_mul(boost_bps)
+            .ok_or(ErrorCode::MathOverflow)?
+            / constants::BPS_DENOMINATOR;
+
+        self.apy
+            .checked_add(boost)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    /// Compounded reward of an autostake position in reward_mint base units. The last
+    /// `boosted_cycles` cycles (applied after the boost) compound at the boosted APY
+    pub fn autostake_reward(
+        &self,
+        staked_amount: u64,
+        boost_bps: u64,
+        cycle_duration: u64,
+        total_cycles: u64,
+        boosted_cycles: u64,
+    ) -> Result<u64> {
+        let current_stake = self.to_reward_units(staked_amount)? as f64;
+
+        // Safely convert the cycle counts to i32
+        let base_cycles: i32 = total_cycles
+            .saturating_sub(boosted_cycles)
+            .try_into()
+            .map_err(|_| ErrorCode::InvalidCycleCount)?;
+        let boosted_cycles: i32 = boosted_cycles
+            .min(total_cycles)
+            .try_into()
+            .map_err(|_| ErrorCode::InvalidCycleCount)?;
+
+        let cycle_share = cycle_duration as f64 / constants::SLOTS_PER_YEAR as f64;
+        let apy_per_cycle = self.apy as f64 / self.apy_denominator as f64 * cycle_share;
+        let boosted_apy_per_cycle =
+            self.effective_apy(boost_bps)? as f64 / self.apy_denominator as f64 * cycle_share;
+
+        let total_reward = current_stake
+            * (1.0 + apy_per_cycle).powi(base_cycles)
+            * (1.0 + boosted_apy_per_cycle).powi(boosted_cycles)
+            - current_stake;
+
+        Ok(total_reward as u64)
+    }
+
+    /// Settles the emissions since `last_update_time` into `acc_reward_per_share`
+    pub fn update_emissions(&mut self, now: i64) -> Result<()> {
+        if self.reward_mode != constants::REWARD_MODE_EMISSIONS {
+            return Ok(());
+        }
+
+        let until = now.min(self.emission_end);
+        if until <= self.last_update_time {
+            return Ok(());
+        }
+
+        // Emissions while nothing is staked stay in the vault
+        if self.total_staked > 0 {
+            let emitted = ((until - self.last_update_time) as u128)
+                .checked_mul(self.reward_rate as u128)
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            self.acc_reward_per_share = emitted
+                .checked_mul(constants::ACC_PRECISION)
+                .map(|value| value / self.total_staked as u128)
+                .and_then(|value| self.acc_reward_per_share.checked_add(value))
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        self.last_update_time = until;
+
+        Ok(())
+    }
+
+    /// Rewards `amount` staked since the start of the pool would have accrued
+    pub fn accrued_rewards(&self, amount: u64) -> Result<u128> {
+        (amount as u128)
+            .checked_mul(self.acc_reward_per_share)
+            .map(|value| value / constants::ACC_PRECISION)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    /// Rewards a position still has outstanding when it is closed: the compounded autostake reward
+    /// and the pending emissions. Settles the emission accumulator up to `now` first
+    pub fn closing_rewards(&mut self, stake_info: &StakeInfo, now: i64) -> Result<(u64, u64)> {
+        let mut autostake_reward = 0;
+
+        if stake_info.autostake {
+            // Determine cycle type (daily/weekly/monthly/etc)
+            let cycle_duration = match self.roi_type {
+                0 => constants::SLOTS_PER_DAY,   // Daily reward calculation
+                1 => constants::SLOTS_PER_WEEK,  // Weekly reward calculation
+                2 => constants::SLOTS_PER_MONTH, // Monthly reward calculation
+                _ => return Err(ErrorCode::InvalidRoiType.into()),
+            };
+
+            // The cycles after the boost was applied compound at the boosted APY
+            let boosted_cycles = stake_info.boosted_cycles(
+                stake_info.stake_at_slot,
+                stake_info.end_time,
+                cycle_duration,
+                stake_info.total_claim_cycles,
+            );
+
+            autostake_reward = self.autostake_reward(
+                stake_info.staked_amount,
+                stake_info.boost_bps,
+                cycle_duration,
+                stake_info.total_claim_cycles,
+                boosted_cycles,
+            )?;
+        }
+
+        let mut pending_rewards = 0;
+
+        if self.reward_mode == constants::REWARD_MODE_EMISSIONS {
+            self.update_emissions(now)?;
+            pending_rewards = self.pending_rewards(stake_info)?;
+        }
+
+        Ok((autostake_reward, pending_rewards))
+    }
+
+    /// Emission rewards of a position that have not been paid yet
+    pub fn pending_rewards(&self, stake_info: &StakeInfo) -> Result<u64> {
+        let pending = self
+            .accrued_rewards(stake_info.staked_amount)?
+            .saturating_sub(stake_info.reward_debt);
+
+        u64::try_from(pending).map_err(|_| ErrorCode::MathOverflow.into())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct Stake<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init_if_needed,
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, signer.key.as_ref(), pool_info.key().as_ref(),  ],
+        bump,
+        payer = signer, 
+        space = 8 + 8 + std::mem::size_of::<StakeInfo>(),
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+
+    #[account(
+        init_if_needed,
+        seeds = [constants::WALLET_STAKE_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + std::mem::size_of::<WalletStake>(),
+    )]
+    pub wallet_stake: Account<'info, WalletStake>,
+
+    /// Wallet stake of the referrer, only read on the first stake of the wallet in the pool
+    #[account(mut)]
+    pub referrer_wallet_stake: Option<Account<'info, WalletStake>>,
+
+    #[account(
+        init_if_needed,
+        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+        payer = signer,
+        token::mint = mint,
+        token::authority = stake_account,
+        token::token_program = token_program,
+    )]
+    pub stake_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.token @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    /// Receipt accounts, only needed in receipt pools
+    #[account(mut, address = pool_info.receipt_mint @ ErrorCode::InvalidMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = receipt_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = receipt_token_program,
+    )]
+    pub user_receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub receipt_token_program: Option<Interface<'info, TokenInterface>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct DeStake<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
+        bump,
+        constraint = stake_info_account.owner == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+
+    // init_if_needed so positions migrated from before wallet tracking can still destake
+    #[account(
+        init_if_needed,
+        seeds = [constants::WALLET_STAKE_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + std::mem::size_of::<WalletStake>(),
+    )]
+    pub wallet_stake: Account<'info, WalletStake>,
+
+    /// Wallet stake of the position's referrer, receives the referral share of the rewards
+    #[account(mut)]
+    pub referrer_wallet_stake: Option<Account<'info, WalletStake>>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.token @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = reward_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = reward_token_program,
+    )]
+    pub user_reward_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    /// Receipt accounts, only needed for positions with receipts
+    #[account(mut, address = pool_info.receipt_mint @ ErrorCode::InvalidMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub user_receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub receipt_token_program: Option<Interface<'info, TokenInterface>>,
+    /// Boost lock and the owner's associated token account of the boost NFT, only needed for
+    /// boosted positions. Without them the boost is dropped
+    #[account(
+        seeds = [constants::BOOST_LOCK_SEED, pool_info.key().as_ref(), stake_info_account.boost_mint.as_ref()],
+        bump,
+    )]
+    pub boost_lock: Option<Account<'info, BoostLock>>,
+    /// CHECK: checked against the owner's associated token account of the boost NFT
+    pub boost_token_account: Option<UncheckedAccount<'info>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub reward_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct Reward<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
+        bump,
+        constraint = stake_info_account.owner == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+
+    /// Wallet stake of the position's referrer, receives the referral share of the rewards
+    #[account(mut)]
+    pub referrer_wallet_stake: Option<Account<'info, WalletStake>>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub stake_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = reward_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_reward_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    /// Receipt accounts, only needed for positions with receipts
+    #[account(mut, address = pool_info.receipt_mint @ ErrorCode::InvalidMint)]
+    pub receipt_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub user_receipt_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub receipt_token_program: Option<Interface<'info, TokenInterface>>,
+    /// Boost lock and the owner's associated token account of the boost NFT, only needed for
+    /// boosted positions. Without them the boost is dropped
+    #[account(
+        seeds = [constants::BOOST_LOCK_SEED, pool_info.key().as_ref(), stake_info_account.boost_mint.as_ref()],
+        bump,
+    )]
+    pub boost_lock: Option<Account<'info, BoostLock>>,
+    /// CHECK: checked against the owner's associated token account of the boost NFT
+    pub boost_token_account: Option<UncheckedAccount<'info>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePoolInfo<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+}
+
+#[derive(Accounts)]
+pub struct EnableReceiptToken<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+    #[account(address = pool_info.token @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        seeds = [constants::RECEIPT_MINT_SEED, pool_info.key().as_ref()],
+        bump,
+        payer = admin,
+        mint::decimals = mint.decimals,
+        mint::authority = receipt_mint,
+        mint::token_program = receipt_token_program,
+    )]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+    pub receipt_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct RedeemReceipt<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: owner of the position, only used for the seeds and as destination of its rewards
+    #[account(address = stake_info_account.owner @ ErrorCode::Unauthorized)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
+        bump,
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::WALLET_STAKE_SEED, owner.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub wallet_stake: Account<'info, WalletStake>,
+
+    /// Wallet stake of the position's referrer, receives the referral share of the rewards
+    #[account(mut)]
+    pub referrer_wallet_stake: Option<Account<'info, WalletStake>>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::TOKEN_SEED, owner.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.token @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = reward_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = reward_token_program,
+    )]
+    pub owner_reward_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = pool_info.receipt_mint @ ErrorCode::InvalidMint)]
+    pub receipt_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = receipt_mint,
+        token::authority = signer,
+        token::token_program = receipt_token_program,
+    )]
+    pub user_receipt_token_account: InterfaceAccount<'info, TokenAccount>,
+    /// Boost lock and the owner's associated token account of the boost NFT, only needed for
+    /// boosted positions. Without them the boost is dropped
+    #[account(
+        seeds = [constants::BOOST_LOCK_SEED, pool_info.key().as_ref(), stake_info_account.boost_mint.as_ref()],
+        bump,
+    )]
+    pub boost_lock: Option<Account<'info, BoostLock>>,
+    /// CHECK: checked against the owner's associated token account of the boost NFT
+    pub boost_token_account: Option<UncheckedAccount<'info>>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub reward_token_program: Interface<'info, TokenInterface>,
+    pub receipt_token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct TransferPosition<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK: wallet that receives the position, only used for the seeds and as the new owner
+    pub new_owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
+        bump,
+        constraint = stake_info_account.owner == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::WALLET_STAKE_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub wallet_stake: Account<'info, WalletStake>,
+
+    #[account(
+        init_if_needed,
+        seeds = [constants::WALLET_STAKE_SEED, new_owner.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + std::mem::size_of::<WalletStake>(),
+    )]
+    pub new_owner_wallet_stake: Account<'info, WalletStake>,
+
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+    )]
+    pub stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        seeds = [constants::TOKEN_SEED, new_owner.key.as_ref(), pool_info.key().as_ref()],
+        bump,
+        payer = signer,
+        token::mint = mint,
+        token::authority = new_owner_stake_account,
+        token::token_program = token_program,
+    )]
+    pub new_owner_stake_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.token @ ErrorCode::InvalidMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
+    )]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AdminWithdraw<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        associated_token::mint = reward_mint,
+        associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLegacyVault<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
         mut,
         seeds = [constants::VAULT_SEED],
         bump,
+        token::mint = reward_mint,
+        token::token_program = token_program,
+    )]
+    pub legacy_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+        payer = signer,
+        token::mint = reward_mint,
+        token::authority = token_vault_account,
+        token::token_program = token_program,
     )]
-    pub token_vault_account: Account<'info, TokenAccount>,
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigratePoolInfo<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: legacy layout, loaded as PoolInfo after the realloc in migrate_pool_info
+    #[account(mut, owner = crate::ID)]
+    pub pool_info: UncheckedAccount<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+}
 
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct MigrateStakeInfo<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: legacy layout, loaded as StakeInfo after the realloc in migrate_stake_info. Legacy
+    /// positions are derived from the staker, which is the signer
     #[account(
         mut,
         seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, signer.key.as_ref(), pool_info.key().as_ref(),  ],
         bump,
+        owner = crate::ID,
     )]
-    pub stake_info_account: Account<'info, StakeInfo>,
+    pub stake_info_account: UncheckedAccount<'info>,
     pub pool_info: Account<'info, PoolInfo>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stake_counter: u64)]
+pub struct ApplyBoost<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
 
     #[account(
         mut,
-        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
         bump,
+        constraint = stake_info_account.owner == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub stake_info_account: Account<'info, StakeInfo>,
+    pub pool_info: Account<'info, PoolInfo>,
+
+    pub nft_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        constraint = nft_token_account.owner == signer.key() @ ErrorCode::InvalidBoostNft,
+        constraint = nft_token_account.mint == nft_mint.key() @ ErrorCode::InvalidBoostNft,
+        constraint = nft_token_account.amount > 0 @ ErrorCode::InvalidBoostNft,
     )]
-    pub stake_account: Account<'info, TokenAccount>,
+    pub nft_token_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut,
-        associated_token::mint = mint,
-        associated_token::authority = signer,
+        seeds = [b"metadata", Metadata::id().as_ref(), nft_mint.key().as_ref()],
+        seeds::program = Metadata::id(),
+        bump,
     )]
-    pub user_token_account: Account<'info, TokenAccount>,
+    pub nft_metadata: Account<'info, MetadataAccount>,
 
-    pub mint: Account<'info, Mint>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Program<'info, Token>,
+    #[account(
+        init_if_needed,
+        seeds = [constants::BOOST_LOCK_SEED, pool_info.key().as_ref(), nft_mint.key().as_ref()],
+        bump,
+        payer = signer,
+        space = 8 + std::mem::size_of::<BoostLock>(),
+    )]
+    pub boost_lock: Account<'info, BoostLock>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 #[instruction(stake_counter: u64)]
-pub struct Reward<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+pub struct RefreshBoost<'info> {
     #[account(
         mut,
-        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, signer.key.as_ref(), pool_info.key().as_ref(),  ],
+        seeds = [ &stake_counter.to_le_bytes().as_ref(), constants::STAKE_INFO_SEED, stake_info_account.staker.as_ref(), pool_info.key().as_ref(),  ],
         bump,
     )]
     pub stake_info_account: Account<'info, StakeInfo>,
     pub pool_info: Account<'info, PoolInfo>,
 
+    /// CHECK: owner's associated token account of the boost NFT, may be closed already
+    pub nft_token_account: UncheckedAccount<'info>,
+
     #[account(
-        mut,
-        seeds = [constants::TOKEN_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
+        seeds = [constants::BOOST_LOCK_SEED, pool_info.key().as_ref(), stake_info_account.boost_mint.as_ref()],
         bump,
     )]
-    pub stake_account: Account<'info, TokenAccount>,
+    pub boost_lock: Account<'info, BoostLock>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferral<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
     #[account(
         mut,
-        seeds = [constants::VAULT_SEED],
+        seeds = [constants::WALLET_STAKE_SEED, signer.key.as_ref(), pool_info.key().as_ref()],
         bump,
     )]
-    pub token_vault_account: Account<'info, TokenAccount>,
+    pub wallet_stake: Account<'info, WalletStake>,
+    pub pool_info: Account<'info, PoolInfo>,
 
     #[account(
         mut,
-        associated_token::mint = mint,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = signer,
+        associated_token::mint = reward_mint,
         associated_token::authority = signer,
+        associated_token::token_program = token_program,
+    )]
+    pub user_reward_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        seeds = [constants::CONFIG_SEED],
+        bump,
+        constraint = !config.paused @ ErrorCode::ProgramPaused
     )]
-    pub user_token_account: Account<'info, TokenAccount>,
-    pub mint: Account<'info, Mint>,
+    pub config: Account<'info, Config>,
     pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct UpdatePoolInfo<'info> {
+pub struct FundEmissions<'info> {
     #[account(mut)]
     pub admin: Signer<'info>,
+
     #[account(mut)]
     pub pool_info: Account<'info, PoolInfo>,
+
+    #[account(
+        mut,
+        seeds = [constants::VAULT_SEED, pool_info.key().as_ref(), pool_info.reward_mint.as_ref()],
+        bump,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = reward_mint,
+        associated_token::authority = admin,
+        associated_token::token_program = token_program,
+    )]
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(address = pool_info.reward_mint @ ErrorCode::InvalidMint)]
+    pub reward_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct AdminWithdraw<'info> {
+pub struct AddRewardStream<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub pool_info: Account<'info, PoolInfo>,
+    #[account(
+        init_if_needed,
+        seeds = [constants::STREAM_VAULT_SEED, pool_info.key().as_ref(), stream_mint.key().as_ref()],
+        bump,
+        payer = admin,
+        token::mint = stream_mint,
+        token::authority = token_vault_account,
+        token::token_program = token_program,
+    )]
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
+    pub stream_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(stream_index: u8)]
+pub struct AdminWithdrawStream<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
 
     #[account(
         mut,
-        seeds = [constants::VAULT_SEED],
+        seeds = [constants::STREAM_VAULT_SEED, pool_info.key().as_ref(), stream_mint.key().as_ref()],
         bump,
     )]
-    pub token_vault_account: Account<'info, TokenAccount>,
+    pub token_vault_account: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(
+        constraint = stream_index < pool_info.reward_stream_count @ ErrorCode::InvalidStreamIndex,
+    )]
     pub pool_info: Account<'info, PoolInfo>,
 
     #[account(
         mut,
-        associated_token::mint = mint,
+        associated_token::mint = stream_mint,
         associated_token::authority = signer,
+        associated_token::token_program = token_program,
     )]
-    pub admin_token_account: Account<'info, TokenAccount>,
+    pub admin_token_account: InterfaceAccount<'info, TokenAccount>,
 
-    pub mint: Account<'info, Mint>,
+    #[account(
+        address = pool_info.reward_streams[stream_index as usize].mint @ ErrorCode::InvalidMint,
+    )]
+    pub stream_mint: InterfaceAccount<'info, Mint>,
     pub associated_token_program: Program<'info, AssociatedToken>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub system_program: Program<'info, System>,
 }
 
 #[account]
+#[derive(Default)]
 pub struct StakeInfo {
     pub staked_amount: u64,
     pub deposit_timestamp: i64,
@@ -761,6 +3167,126 @@ pub struct StakeInfo {
     pub claim_cycles_passed: u64,
     pub stake_seed: u64,
     pub in_process: bool,
+    pub stream_last_slot: [u64; constants::MAX_REWARD_STREAMS],
+    pub stream_claimed: [u64; constants::MAX_REWARD_STREAMS],
+    pub reward_debt: u128,
+    pub deposited_amount: u64,
+    pub referrer: Pubkey,
+    pub boost_bps: u64,
+    pub boost_mint: Pubkey,
+    pub boost_collection: Pubkey,
+    pub receipt_amount: u64,
+    pub owner: Pubkey,
+    pub staker: Pubkey,
+    pub boost_start_slot: u64,
+    pub version: u8, // LAYOUT_VERSION, 0 = legacy position that needs migrate_stake_info
+}
+
+impl StakeInfo {
+    /// Settles what every reward stream accrued on the current principal since the last
+    /// payout, pro rata per slot, and returns the amount owed per stream
+    pub fn settle_streams(
+        &mut self,
+        pool_info: &PoolInfo,
+        slot: u64,
+    ) -> Result<[u64; constants::MAX_REWARD_STREAMS]> {
+        let mut rewards = [0; constants::MAX_REWARD_STREAMS];
+
+        let stream_count = pool_info.reward_stream_count as usize;
+
+        for (index, stream) in pool_info.reward_streams.iter().take(stream_count).enumerate() {
+            // Streams only accrue inside their own period and the lock period of the stake
+            let accrue_from = self.stream_last_slot[index]
+                .max(stream.start_slot)
+                .max(self.stake_at_slot);
+            let accrue_to = slot.min(stream.end_slot).min(self.end_time);
+
+            if accrue_to <= accrue_from {
+                continue;
+            }
+
+            let stake_in_stream_units = pool_info.to_units(self.staked_amount, stream.decimals)?;
+            let reward = (stake_in_stream_units as u128)
+                .checked_mul(stream.apy as u128)
+                .and_then(|value| value.checked_mul((accrue_to - accrue_from) as u128))
+                .and_then(|value| {
+                    value.checked_div(stream.apy_denominator as u128 * constants::SLOTS_PER_YEAR as u128)
+                })
+                .and_then(|value| u64::try_from(value).ok())
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            self.stream_last_slot[index] = accrue_to;
+            self.stream_claimed[index] += reward;
+            rewards[index] = reward;
+        }
+
+        Ok(rewards)
+    }
+
+    /// Adds restaked rewards to the principal. The streams are settled first so the slots up to
+    /// `slot` are paid on the principal that was actually staked during them
+    pub fn restake(
+        &mut self,
+        pool_info: &mut PoolInfo,
+        amount: u64,
+        slot: u64,
+    ) -> Result<[u64; constants::MAX_REWARD_STREAMS]> {
+        let stream_rewards = self.settle_streams(pool_info, slot)?;
+
+        self.staked_amount = self.staked_amount.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+        pool_info.total_staked = pool_info.total_staked.checked_add(amount).ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(stream_rewards)
+    }
+
+    /// Checks that the receipt of the position can be redeemed at `slot`
+    pub fn assert_redeemable(&self, slot: u64) -> Result<()> {
+        //Ensure that the stake record exists
+        if !self.is_staked {
+            return Err(ErrorCode::NotStaked.into());
+        }
+
+        //Ensure that the position was opened with receipt tokens
+        if self.receipt_amount == 0 {
+            return Err(ErrorCode::NoReceipt.into());
+        }
+
+        //Ensure that the position is not redeemed before lock period is over
+        if slot < self.end_time {
+            return Err(ErrorCode::StillLocked.into());
+        }
+
+        //Ensure non re-entrance
+        if self.in_process {
+            return Err(ErrorCode::AlreadyInProcess.into());
+        }
+
+        Ok(())
+    }
+
+    /// Whole cycles between `from` and `to` that lie after the boost was applied, at most `cycles`
+    pub fn boosted_cycles(&self, from: u64, to: u64, cycle_length: u64, cycles: u64) -> u64 {
+        if self.boost_bps == 0 {
+            return 0;
+        }
+
+        (to.saturating_sub(from.max(self.boost_start_slot)) / cycle_length).min(cycles)
+    }
+
+    /// Boost on top of `reward_for_cycle` for `cycles` boosted cycles
+    pub fn boost_reward(&self, reward_for_cycle: u64, cycles: u64) -> Result<u64> {
+        (reward_for_cycle as u128)
+            .checked_mul(self.boost_bps as u128)
+            .and_then(|value| value.checked_mul(cycles as u128))
+            .map(|value| value / constants::BPS_DENOMINATOR as u128)
+            .and_then(|value| u64::try_from(value).ok())
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+}
+
+#[account]
+pub struct BoostLock {
+    pub position: Pubkey, // the only position the NFT boosts, moved by apply_boost
 }
 
 #[error_code]
@@ -803,4 +3329,468 @@ pub enum ErrorCode {
     AlreadyInProcess,
     #[msg("Cycle count exceed i32 range")]
     InvalidCycleCount,
+    #[msg("Program is paused, only destake is available")]
+    ProgramPaused,
+    #[msg("Mint has a Token-2022 extension that is not supported")]
+    UnsupportedMintExtension,
+    #[msg("Mint does not match the pool token")]
+    InvalidMint,
+    #[msg("Rewards can only be restaked when they are paid in the staked token")]
+    RestakeNotSupported,
+    #[msg("Arithmetic overflow")]
+    MathOverflow,
+    #[msg("Reward stream must end after it starts")]
+    InvalidStreamPeriod,
+    #[msg("The pool already has the maximum number of reward streams")]
+    TooManyRewardStreams,
+    #[msg("Reward stream does not exist")]
+    InvalidStreamIndex,
+    #[msg("Reward stream accounts are missing or invalid")]
+    InvalidRemainingAccounts,
+    #[msg("Instruction is not available for the reward mode of this pool")]
+    InvalidRewardMode,
+    #[msg("Invalid reward rate: cannot be zero.")]
+    InvalidRewardRate,
+    #[msg("Emission end must be in the future")]
+    InvalidEmissionEnd,
+    #[msg("Deposits for this pool have not opened yet")]
+    DepositsNotOpen,
+    #[msg("Deposits for this pool are closed")]
+    DepositsClosed,
+    #[msg("Stake is below the pool minimum")]
+    StakeBelowMinimum,
+    #[msg("Stake is above the pool maximum per position")]
+    StakeAboveMaximum,
+    #[msg("Stake exceeds the pool limit per wallet")]
+    WalletCapExceeded,
+    #[msg("Pool capacity reached")]
+    PoolCapacityReached,
+    #[msg("Invalid pool limits: minimum above maximum or deposit window ends before it starts")]
+    InvalidPoolLimits,
+    #[msg("Wallet is not on the allowlist of this pool")]
+    NotAllowlisted,
+    #[msg("Stake exceeds the allowance of this wallet")]
+    AllowanceExceeded,
+    #[msg("Referrer account is missing or invalid")]
+    InvalidReferrer,
+    #[msg("Invalid referral share: cannot exceed 10000 basis points")]
+    InvalidReferralBps,
+    #[msg("This pool has no NFT boost")]
+    BoostNotConfigured,
+    #[msg("NFT is not held by the wallet or not part of the verified collection of the pool")]
+    InvalidBoostNft,
+    #[msg("No boost applied to this position")]
+    BoostNotApplied,
+    #[msg("Invalid boost: cannot exceed MAX_BOOST_BPS")]
+    InvalidBoostBps,
+    #[msg("Receipt token accounts are missing or invalid")]
+    InvalidReceiptAccounts,
+    #[msg("This position has no receipt tokens")]
+    NoReceipt,
+    #[msg("Positions of this pool can not be transferred")]
+    PositionTransfersDisabled,
+    #[msg("Position can only be transferred to another wallet")]
+    InvalidNewOwner,
+    #[msg("Positions of transfer fee mints can not be transferred")]
+    TransferFeeNotSupported,
+    #[msg("Positions with receipt tokens can not be transferred, transfer the receipts instead")]
+    ReceiptPositionNotTransferable,
+    #[msg("Account already uses the current layout")]
+    AlreadyMigrated,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::Discriminator;
+
+    /// Pool and position the tests start from: a monthly 10% APY pool of a 9 decimal
+    /// token and an open position of one token in it
+    struct Fixture {
+        pool_info: PoolInfo,
+        stake_info: StakeInfo,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Fixture {
+                pool_info: PoolInfo {
+                    apy: 10,
+                    apy_denominator: 100,
+                    roi_type: 2,
+                    token_decimals: 9,
+                    reward_decimals: 9,
+                    ..Default::default()
+                },
+                stake_info: StakeInfo {
+                    is_staked: true,
+                    staked_amount: 1_000_000_000,
+                    ..Default::default()
+                },
+            }
+        }
+
+        fn locked_until(mut self, end_time: u64) -> Self {
+            self.stake_info.end_time = end_time;
+            self
+        }
+
+        fn claim_cycles(mut self, total_claim_cycles: u64) -> Self {
+            self.stake_info.total_claim_cycles = total_claim_cycles;
+            self
+        }
+
+        fn receipt(mut self) -> Self {
+            self.stake_info.receipt_amount = self.stake_info.staked_amount;
+            self
+        }
+
+        fn boost(mut self, boost_bps: u64, boost_start_slot: u64) -> Self {
+            self.stake_info.boost_bps = boost_bps;
+            self.stake_info.boost_start_slot = boost_start_slot;
+            self
+        }
+
+        fn total_staked(mut self, total_staked: u64) -> Self {
+            self.pool_info.total_staked = total_staked;
+            self
+        }
+
+        fn emissions(mut self, reward_rate: u64, emission_end: i64) -> Self {
+            self.pool_info.reward_mode = constants::REWARD_MODE_EMISSIONS;
+            self.pool_info.reward_rate = reward_rate;
+            self.pool_info.emission_end = emission_end;
+            self
+        }
+
+        /// Adds a 10% APY stream of a 9 decimal mint running for a year
+        fn stream(mut self) -> Self {
+            let index = self.pool_info.reward_stream_count as usize;
+            self.pool_info.reward_streams[index] = RewardStream {
+                apy: 10,
+                apy_denominator: 100,
+                end_slot: constants::SLOTS_PER_YEAR,
+                decimals: 9,
+                ..Default::default()
+            };
+            self.pool_info.reward_stream_count += 1;
+            self
+        }
+
+        fn build(self) -> (PoolInfo, StakeInfo) {
+            (self.pool_info, self.stake_info)
+        }
+    }
+
+    #[test]
+    fn redeem_receipt_after_lock() {
+        let (_, stake_info) = Fixture::new().receipt().locked_until(500).build();
+
+        assert!(stake_info.assert_redeemable(500).is_ok());
+        assert!(stake_info.assert_redeemable(501).is_ok());
+    }
+
+    #[test]
+    fn redeem_receipt_still_locked() {
+        let (_, stake_info) = Fixture::new().receipt().locked_until(500).build();
+
+        assert_eq!(
+            stake_info.assert_redeemable(499).unwrap_err(),
+            ErrorCode::StillLocked.into()
+        );
+    }
+
+    #[test]
+    fn redeem_receipt_needs_open_receipt_position() {
+        let (_, mut stake_info) = Fixture::new().locked_until(500).build();
+
+        assert_eq!(
+            stake_info.assert_redeemable(500).unwrap_err(),
+            ErrorCode::NoReceipt.into()
+        );
+
+        stake_info.is_staked = false;
+
+        assert_eq!(
+            stake_info.assert_redeemable(500).unwrap_err(),
+            ErrorCode::NotStaked.into()
+        );
+    }
+
+    #[test]
+    fn redeem_receipt_in_process() {
+        let (_, mut stake_info) = Fixture::new().receipt().locked_until(500).build();
+        stake_info.in_process = true;
+
+        assert_eq!(
+            stake_info.assert_redeemable(500).unwrap_err(),
+            ErrorCode::AlreadyInProcess.into()
+        );
+    }
+
+    #[test]
+    fn boost_counts_cycles_after_it_was_applied() {
+        let (_, stake_info) = Fixture::new().boost(5_000, 250).build();
+
+        // cycles of 100 slots from slot 0 to 1000, the boost covers 250..1000
+        assert_eq!(stake_info.boosted_cycles(0, 1_000, 100, 10), 7);
+        assert_eq!(stake_info.boosted_cycles(500, 1_000, 100, 10), 5);
+        assert_eq!(stake_info.boosted_cycles(0, 1_000, 100, 3), 3);
+        assert_eq!(stake_info.boosted_cycles(0, 200, 100, 10), 0);
+
+        let (_, unboosted) = Fixture::new().build();
+        assert_eq!(unboosted.boosted_cycles(0, 1_000, 100, 10), 0);
+    }
+
+    #[test]
+    fn boost_reward_per_cycle() {
+        let (_, stake_info) = Fixture::new().boost(5_000, 0).build();
+
+        assert_eq!(stake_info.boost_reward(1_000, 3).unwrap(), 1_500);
+        assert_eq!(stake_info.boost_reward(1_000, 0).unwrap(), 0);
+        assert_eq!(
+            stake_info.boost_reward(u64::MAX, 3).unwrap_err(),
+            ErrorCode::MathOverflow.into()
+        );
+    }
+
+    #[test]
+    fn boosted_apy() {
+        let (pool_info, _) = Fixture::new().build();
+
+        assert_eq!(pool_info.effective_apy(0).unwrap(), 10);
+        assert_eq!(pool_info.effective_apy(5_000).unwrap(), 15);
+        assert_eq!(
+            pool_info.effective_apy(constants::MAX_BOOST_BPS).unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn autostake_reward_compounds_boosted_cycles() {
+        let (pool_info, stake_info) = Fixture::new().build();
+        let staked_amount = stake_info.staked_amount;
+        let cycles = 12;
+
+        let unboosted = pool_info
+            .autostake_reward(staked_amount, 5_000, constants::SLOTS_PER_MONTH, cycles, 0)
+            .unwrap();
+        let partly_boosted = pool_info
+            .autostake_reward(staked_amount, 5_000, constants::SLOTS_PER_MONTH, cycles, 6)
+            .unwrap();
+        let fully_boosted = pool_info
+            .autostake_reward(staked_amount, 5_000, constants::SLOTS_PER_MONTH, cycles, cycles)
+            .unwrap();
+
+        assert_eq!(
+            unboosted,
+            pool_info
+                .autostake_reward(staked_amount, 0, constants::SLOTS_PER_MONTH, cycles, cycles)
+                .unwrap()
+        );
+        assert!(unboosted < partly_boosted);
+        assert!(partly_boosted < fully_boosted);
+
+        // boosted cycles beyond the position's cycles are ignored
+        assert_eq!(
+            fully_boosted,
+            pool_info
+                .autostake_reward(staked_amount, 5_000, constants::SLOTS_PER_MONTH, cycles, 20)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn closing_rewards_of_autostake_and_emission_positions() {
+        let (mut pool_info, mut stake_info) = Fixture::new()
+            .locked_until(12 * constants::SLOTS_PER_MONTH)
+            .claim_cycles(12)
+            .build();
+
+        assert_eq!(pool_info.closing_rewards(&stake_info, 0).unwrap(), (0, 0));
+
+        stake_info.autostake = true;
+        let compounded = pool_info
+            .autostake_reward(stake_info.staked_amount, 0, constants::SLOTS_PER_MONTH, 12, 0)
+            .unwrap();
+        assert_eq!(pool_info.closing_rewards(&stake_info, 0).unwrap(), (compounded, 0));
+
+        // 100 seconds of 10 units per second, the position holds half of the pool
+        let (mut pool_info, stake_info) = Fixture::new()
+            .total_staked(2_000_000_000)
+            .emissions(10, 1_000)
+            .build();
+        assert_eq!(pool_info.closing_rewards(&stake_info, 100).unwrap(), (0, 500));
+        assert_eq!(pool_info.last_update_time, 100);
+    }
+
+    #[test]
+    fn restake_settles_streams_on_the_original_principal() {
+        let (mut pool_info, mut stake_info) = Fixture::new()
+            .total_staked(1_000_000_000)
+            .stream()
+            .locked_until(constants::SLOTS_PER_YEAR)
+            .build();
+        let half_year = constants::SLOTS_PER_YEAR / 2;
+
+        // half a year at 10% on the 1_000_000_000 staked before the restake
+        let stream_rewards = stake_info.restake(&mut pool_info, 500_000_000, half_year).unwrap();
+        assert_eq!(stream_rewards[0], 50_000_000);
+        assert_eq!(stake_info.staked_amount, 1_500_000_000);
+        assert_eq!(pool_info.total_staked, 1_500_000_000);
+
+        // the restaked amount only accrues from the restake on
+        let stream_rewards = stake_info
+            .settle_streams(&pool_info, constants::SLOTS_PER_YEAR)
+            .unwrap();
+        assert_eq!(stream_rewards[0], 75_000_000);
+        assert_eq!(stake_info.stream_claimed[0], 125_000_000);
+    }
+
+    #[derive(AnchorSerialize)]
+    struct LegacyPoolInfo {
+        admin: Pubkey,
+        token_vault: Pubkey,
+        lock_time: u64,
+        apy: u64,
+        apy_denominator: u64,
+        roi_type: u64,
+        token: Pubkey,
+    }
+
+    #[derive(AnchorSerialize, Default)]
+    struct LegacyStakeInfo {
+        staked_amount: u64,
+        deposit_timestamp: i64,
+        stake_at_slot: u64,
+        is_staked: bool,
+        end_time: u64,
+        autostake: bool,
+        unclaimed_rewards: u64,
+        last_interaction_time: u64,
+        next_claim_time: u64,
+        pool_info: Pubkey,
+        total_claimed: u64,
+        total_claim_cycles: u64,
+        claim_cycles_passed: u64,
+        stake_seed: u64,
+        in_process: bool,
+    }
+
+    /// Account data of a legacy account after realloc_legacy_account grew it to `space`
+    fn realloced(discriminator: [u8; 8], legacy: impl AnchorSerialize, space: usize) -> Vec<u8> {
+        let mut data = discriminator.to_vec();
+        data.extend(legacy.try_to_vec().unwrap());
+        data.resize(space, 0);
+        data
+    }
+
+    #[test]
+    fn legacy_pool_info_loads_after_realloc() {
+        let (admin, token) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let legacy = LegacyPoolInfo {
+            admin,
+            token_vault: Pubkey::new_unique(),
+            lock_time: constants::SLOTS_PER_MONTH,
+            apy: 10,
+            apy_denominator: 100,
+            roi_type: 2,
+            token,
+        };
+        let data = realloced(PoolInfo::DISCRIMINATOR, legacy, 8 + std::mem::size_of::<PoolInfo>());
+
+        let pool_info = PoolInfo::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(pool_info.admin, admin);
+        assert_eq!(pool_info.token, token);
+        assert_eq!(pool_info.lock_time, constants::SLOTS_PER_MONTH);
+        assert_eq!((pool_info.apy, pool_info.apy_denominator, pool_info.roi_type), (10, 100, 2));
+        assert_eq!(pool_info.reward_mint, Pubkey::default());
+        assert_eq!(pool_info.version, 0);
+    }
+
+    #[test]
+    fn legacy_stake_info_loads_after_realloc() {
+        let pool = Pubkey::new_unique();
+        let legacy = LegacyStakeInfo {
+            staked_amount: 1_000,
+            is_staked: true,
+            end_time: 500,
+            pool_info: pool,
+            stake_seed: 3,
+            ..Default::default()
+        };
+        let data = realloced(
+            StakeInfo::DISCRIMINATOR,
+            legacy,
+            8 + 8 + std::mem::size_of::<StakeInfo>(),
+        );
+
+        let stake_info = StakeInfo::try_deserialize(&mut &data[..]).unwrap();
+        assert_eq!(stake_info.staked_amount, 1_000);
+        assert!(stake_info.is_staked);
+        assert_eq!(stake_info.end_time, 500);
+        assert_eq!(stake_info.pool_info, pool);
+        assert_eq!(stake_info.stake_seed, 3);
+        assert_eq!(stake_info.owner, Pubkey::default());
+        assert_eq!(stake_info.version, 0);
+
+        // A legacy account is too short for the current layout until it is grown
+        let mut legacy_data = StakeInfo::DISCRIMINATOR.to_vec();
+        legacy_data.extend(LegacyStakeInfo::default().try_to_vec().unwrap());
+        assert!(StakeInfo::try_deserialize(&mut &legacy_data[..]).is_err());
+    }
+
+    fn allowlist_leaf(wallet: &Pubkey, max_allowed: u64) -> [u8; 32] {
+        keccak::hashv(&[wallet.as_ref(), &max_allowed.to_le_bytes()]).0
+    }
+
+    fn hash_pair(left: [u8; 32], right: [u8; 32]) -> [u8; 32] {
+        if left <= right {
+            keccak::hashv(&[&left, &right]).0
+        } else {
+            keccak::hashv(&[&right, &left]).0
+        }
+    }
+
+    #[test]
+    fn merkle_allowlist_proves_every_leaf() {
+        let wallets: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let leaves: Vec<[u8; 32]> = wallets
+            .iter()
+            .enumerate()
+            .map(|(index, wallet)| allowlist_leaf(wallet, index as u64 * 1_000))
+            .collect();
+
+        let left = hash_pair(leaves[0], leaves[1]);
+        let right = hash_pair(leaves[2], leaves[3]);
+        let root = hash_pair(left, right);
+
+        assert!(verify_merkle_proof(&[leaves[1], right], root, leaves[0]));
+        assert!(verify_merkle_proof(&[leaves[0], right], root, leaves[1]));
+        assert!(verify_merkle_proof(&[leaves[3], left], root, leaves[2]));
+        assert!(verify_merkle_proof(&[leaves[2], left], root, leaves[3]));
+    }
+
+    #[test]
+    fn merkle_allowlist_rejects_wrong_leaf_or_proof() {
+        let (wallet, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let leaf = allowlist_leaf(&wallet, 1_000);
+        let sibling = allowlist_leaf(&other, 0);
+        let root = hash_pair(leaf, sibling);
+
+        // a higher max_allowed or another wallet is a different leaf
+        assert!(!verify_merkle_proof(&[sibling], root, allowlist_leaf(&wallet, 2_000)));
+        assert!(!verify_merkle_proof(&[sibling], root, allowlist_leaf(&Pubkey::new_unique(), 1_000)));
+
+        // the proof must lead to the root
+        assert!(!verify_merkle_proof(&[], root, leaf));
+        assert!(!verify_merkle_proof(&[leaf], root, leaf));
+    }
+
+    #[test]
+    fn merkle_allowlist_single_leaf() {
+        let leaf = allowlist_leaf(&Pubkey::new_unique(), 0);
+
+        assert!(verify_merkle_proof(&[], leaf, leaf));
+    }
 }